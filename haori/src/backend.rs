@@ -1,47 +1,160 @@
 mod connection;
 mod protocol;
 
-use crate::{Error, endpoint};
+use crate::{Error, client, endpoint};
 use futures::{StreamExt, TryStreamExt};
+use std::collections::BTreeMap;
+use std::future;
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub(super) struct Config {
     connection: connection::Config,
     protocol: protocol::Config,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
 }
 
-pub(super) async fn watch(
-    config: Vec<Config>,
+impl Config {
+    pub(super) fn check(&self) -> Result<(), Error> {
+        connection::check(&self.connection)?;
+        protocol::check(&self.protocol)
+    }
+}
+
+type DiscoverStream =
+    futures::stream::BoxStream<'static, Vec<(ProbeStream, futures::future::AbortRegistration)>>;
+type ProbeStream = futures::stream::BoxStream<
+    'static,
+    Result<
+        (
+            uuid::Uuid,
+            client::Client,
+            endpoint::Metadata,
+            Vec<schemas::Provider>,
+        ),
+        Error,
+    >,
+>;
+
+pub(super) fn validate(config: &[Config]) -> Result<(), Error> {
+    let resolver = resolver()?;
+    for config in config {
+        discover(resolver.clone(), config.clone())?;
+    }
+    Ok(())
+}
+
+pub(super) fn watch(
+    mut rx: tokio::sync::watch::Receiver<Vec<Config>>,
 ) -> Result<impl futures::Stream<Item = Vec<endpoint::Endpoint>>, Error> {
+    struct State {
+        resolver: hickory_resolver::TokioResolver,
+        abort_guards: Vec<(Config, misc::future::AbortGuard)>,
+    }
+
+    impl State {
+        fn update(
+            &mut self,
+            config: Vec<Config>,
+        ) -> Result<Vec<(DiscoverStream, futures::future::AbortRegistration)>, Error> {
+            let mut running = self
+                .abort_guards
+                .iter()
+                .map(|(config, _)| config)
+                .collect::<Vec<_>>();
+            let mut discover_streams = Vec::new();
+            for config in &config {
+                if let Some(index) = running.iter().position(|running| *running == config) {
+                    running.swap_remove(index);
+                } else {
+                    discover_streams.push(discover(self.resolver.clone(), config.clone())?);
+                }
+            }
+
+            let mut discover_streams = discover_streams.into_iter();
+            let mut abort_guards = Vec::new();
+            let mut item = Vec::new();
+            for config in config {
+                if let Some(index) = self
+                    .abort_guards
+                    .iter()
+                    .position(|(running, _)| *running == config)
+                {
+                    abort_guards.push(self.abort_guards.swap_remove(index));
+                } else if let Some(discover_stream) = discover_streams.next() {
+                    let (abort_guard, abort_registration) = misc::future::AbortGuard::new_pair();
+                    item.push((discover_stream, abort_registration));
+                    abort_guards.push((config, abort_guard));
+                }
+            }
+            self.abort_guards = abort_guards;
+            Ok(item)
+        }
+    }
+
+    let mut state = State {
+        resolver: resolver()?,
+        abort_guards: Vec::new(),
+    };
+    let initial = state.update(rx.borrow_and_update().clone())?;
+    let source_stream =
+        futures::stream::once(future::ready(initial)).chain(futures::stream::unfold(
+            (state, tokio_stream::wrappers::WatchStream::from_changes(rx)),
+            async |(mut state, mut rx)| {
+                loop {
+                    let config = rx.next().await?;
+                    match state.update(config) {
+                        Ok(item) => break Some((item, (state, rx))),
+                        Err(e) => tracing::error!(error = e.to_string()),
+                    }
+                }
+            },
+        ));
+    let stream = misc::backend::discover_and_probe(
+        source_stream.boxed(),
+        |(id, client, metadata, providers)| endpoint::Endpoint {
+            id: *id,
+            client: client.clone(),
+            metadata: metadata.clone(),
+            providers: providers.clone(),
+        },
+    );
+    Ok(stream)
+}
+
+fn resolver() -> Result<hickory_resolver::TokioResolver, Error> {
     let mut builder = hickory_resolver::TokioResolver::builder_tokio()?;
     builder.options_mut().ip_strategy = hickory_resolver::config::LookupIpStrategy::Ipv4AndIpv6;
     builder.options_mut().cache_size = 0;
     builder.options_mut().try_tcp_on_error = true;
-    let resolver = builder.build()?;
+    Ok(builder.build()?)
+}
 
-    let mut discover_streams = Vec::new();
-    for config in config {
-        let protocol = config.protocol;
-        let discover_stream = connection::watch(resolver.clone(), config.connection)
-            .await?
-            .map(move |item| {
-                let protocol = protocol.clone();
-                item.into_iter().map(move |(client, abort_registration)| {
+fn discover(
+    resolver: hickory_resolver::TokioResolver,
+    config: Config,
+) -> Result<DiscoverStream, Error> {
+    let protocol = config.protocol;
+    let labels = config.labels;
+    let stream = connection::watch(resolver, config.connection)?
+        .map(move |item| {
+            let protocol = protocol.clone();
+            let labels = labels.clone();
+            item.into_iter()
+                .map(move |(client, mut metadata, abort_registration)| {
                     let id = uuid::Uuid::new_v4();
+                    metadata.labels = labels
+                        .iter()
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .chain(metadata.labels)
+                        .collect();
                     let probe_stream = protocol::watch(client.clone(), protocol.clone())
-                        .map_ok(move |providers| (id, client.clone(), providers));
+                        .map_ok(move |providers| (id, client.clone(), metadata.clone(), providers));
                     (probe_stream.boxed(), abort_registration)
                 })
-            });
-        discover_streams.push(discover_stream.boxed());
-    }
-    let stream = misc::backend::discover_and_probe(discover_streams, |(id, client, providers)| {
-        endpoint::Endpoint {
-            id: *id,
-            client: client.clone(),
-            providers: providers.clone(),
-        }
-    });
+                .collect()
+        })
+        .boxed();
     Ok(stream)
 }
@@ -1,32 +1,57 @@
+mod file;
+mod kubernetes;
 mod standard;
 mod tunnel;
 
-use crate::{Error, client};
+use crate::{Error, client, endpoint};
 use futures::StreamExt;
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub(super) struct Config(Inner);
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 enum Inner {
     #[serde(rename = "standard")]
     Standard(standard::Config),
     #[serde(rename = "tunnel")]
     Tunnel(tunnel::Config),
+    #[serde(rename = "kubernetes")]
+    Kubernetes(kubernetes::Config),
+    #[serde(rename = "file")]
+    File(file::Config),
 }
 
-pub(super) async fn watch(
+pub(super) fn watch(
     resolver: hickory_resolver::TokioResolver,
     config: Config,
 ) -> Result<
-    impl futures::Stream<Item = Vec<(client::Client, futures::future::AbortRegistration)>> + Send,
+    impl futures::Stream<
+        Item = Vec<(
+            client::Client,
+            endpoint::Metadata,
+            futures::future::AbortRegistration,
+        )>,
+    > + Send,
     Error,
 > {
     let stream = match config.0 {
-        Inner::Standard(config) => standard::watch(resolver.clone(), config)?.left_stream(),
-        Inner::Tunnel(config) => tunnel::watch(config).await?.right_stream(),
+        Inner::Standard(config) => standard::watch(resolver.clone(), config)?
+            .left_stream()
+            .left_stream(),
+        Inner::Tunnel(config) => tunnel::watch(config).right_stream().left_stream(),
+        Inner::Kubernetes(config) => kubernetes::watch(config)?.left_stream().right_stream(),
+        Inner::File(config) => file::watch(config).right_stream().right_stream(),
     };
     Ok(stream)
 }
+
+pub(super) fn check(config: &Config) -> Result<(), Error> {
+    match &config.0 {
+        Inner::Standard(config) => standard::check(config),
+        Inner::Tunnel(config) => tunnel::check(config),
+        Inner::Kubernetes(config) => kubernetes::check(config),
+        Inner::File(_) => Ok(()),
+    }
+}
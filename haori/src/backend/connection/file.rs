@@ -0,0 +1,148 @@
+use crate::{Error, client, config, endpoint};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Config {
+    path: PathBuf,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    interval: Duration,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    backends: Vec<Backend>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Backend {
+    #[serde(with = "http_serde::uri")]
+    uri: http::Uri,
+    #[serde(default)]
+    http2_prior_knowledge: bool,
+    resolve: Option<SocketAddr>,
+    unix_socket: Option<PathBuf>,
+    authorization: Option<config::Authorization>,
+    tls: Option<config::Tls>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+}
+
+pub(super) fn watch(
+    config: Config,
+) -> impl futures::Stream<
+    Item = Vec<(
+        client::Client,
+        endpoint::Metadata,
+        futures::future::AbortRegistration,
+    )>,
+> + Send {
+    struct State {
+        config: Config,
+        interval: misc::time::Interval,
+        modified: Option<SystemTime>,
+        abort_guards: Vec<(Backend, misc::future::AbortGuard)>,
+    }
+
+    impl State {
+        async fn next(
+            &mut self,
+        ) -> Vec<(
+            client::Client,
+            endpoint::Metadata,
+            futures::future::AbortRegistration,
+        )> {
+            loop {
+                self.interval.tick().await;
+                let modified = tokio::fs::metadata(&self.config.path)
+                    .await
+                    .and_then(|metadata| metadata.modified())
+                    .inspect_err(|e| tracing::warn!(error = e.to_string()))
+                    .ok();
+                if self.modified.is_some() && modified == self.modified {
+                    continue;
+                }
+
+                match self.load().await {
+                    Ok(backends) => {
+                        self.modified = modified;
+                        break self.update(backends);
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = e.to_string());
+                        break Vec::new();
+                    }
+                }
+            }
+        }
+
+        async fn load(&self) -> Result<Vec<Backend>, Error> {
+            let mut value = config::parse(
+                &self.config.path,
+                &tokio::fs::read(&self.config.path).await?,
+            )?;
+            config::interpolate(&mut value)?;
+            let file = serde_json::from_value::<File>(value)?;
+            Ok(file.backends)
+        }
+
+        fn update(
+            &mut self,
+            backends: Vec<Backend>,
+        ) -> Vec<(
+            client::Client,
+            endpoint::Metadata,
+            futures::future::AbortRegistration,
+        )> {
+            let mut abort_guards = Vec::new();
+            let mut item = Vec::new();
+            for backend in backends {
+                if let Some(index) = self
+                    .abort_guards
+                    .iter()
+                    .position(|(running, _)| *running == backend)
+                {
+                    abort_guards.push(self.abort_guards.swap_remove(index));
+                } else {
+                    let config = client::standard::Config {
+                        uri: backend.uri.clone(),
+                        http2_prior_knowledge: backend.http2_prior_knowledge,
+                        resolve: backend.resolve,
+                        unix_socket: backend.unix_socket.clone(),
+                        authorization: backend.authorization.clone(),
+                        tls: backend.tls.clone(),
+                    };
+                    match client::Client::standard(config) {
+                        Ok(client) => {
+                            let (abort_guard, abort_registration) =
+                                misc::future::AbortGuard::new_pair();
+                            let metadata = endpoint::Metadata {
+                                labels: backend.labels.clone(),
+                                ..endpoint::Metadata::default()
+                            };
+                            item.push((client, metadata, abort_registration));
+                            abort_guards.push((backend, abort_guard));
+                        }
+                        Err(e) => tracing::error!(error = e.to_string()),
+                    }
+                }
+            }
+            self.abort_guards = abort_guards;
+            item
+        }
+    }
+
+    let state = State {
+        interval: misc::time::interval(config.interval),
+        config,
+        modified: None,
+        abort_guards: Vec::new(),
+    };
+    futures::stream::unfold(state, async |mut state| Some((state.next().await, state)))
+}
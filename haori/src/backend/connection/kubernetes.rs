@@ -0,0 +1,469 @@
+use crate::{Error, client, config, endpoint};
+use futures::{StreamExt, TryStreamExt};
+use http_body_util::BodyExt;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Config {
+    api_server: ApiServer,
+    namespace: String,
+    service: String,
+    port: Option<String>,
+    #[serde(with = "http_serde::uri")]
+    #[schemars(with = "String")]
+    uri: http::Uri,
+    #[serde(default)]
+    http2_prior_knowledge: bool,
+    authorization: Option<config::Authorization>,
+    tls: Option<config::Tls>,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    retry_delay: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct ApiServer {
+    #[serde(with = "http_serde::uri")]
+    #[schemars(with = "String")]
+    uri: http::Uri,
+    authorization: Option<config::Authorization>,
+    tls: Option<config::Tls>,
+}
+
+pub(super) fn check(config: &Config) -> Result<(), Error> {
+    if config.uri.port().is_some() {
+        Err("`uri` must not have a port")?
+    }
+    for authorization in [&config.api_server.authorization, &config.authorization]
+        .into_iter()
+        .flatten()
+    {
+        authorization.check()?;
+    }
+    Ok(())
+}
+
+pub(super) fn watch(
+    config: Config,
+) -> Result<
+    impl futures::Stream<
+        Item = Vec<(
+            client::Client,
+            endpoint::Metadata,
+            futures::future::AbortRegistration,
+        )>,
+    > + Send,
+    Error,
+> {
+    struct State {
+        api_server: client::Client,
+        config: Config,
+        resource_version: Option<String>,
+        lines: Option<futures::stream::BoxStream<'static, Result<bytes::Bytes, Error>>>,
+        slices: HashMap<String, EndpointSlice>,
+        abort_guards: HashMap<SocketAddr, misc::future::AbortGuard>,
+        sleep: Option<tokio::time::Sleep>,
+    }
+
+    impl State {
+        async fn next(&mut self) -> Result<(), Error> {
+            #[derive(Debug, thiserror::Error)]
+            #[error("{0:?}")]
+            struct StatusError(http::Response<bytes::Bytes>);
+
+            #[derive(Debug, thiserror::Error)]
+            #[error("watch error: {0}")]
+            struct WatchError(serde_json::Value);
+
+            let path = format!(
+                "/apis/discovery.k8s.io/v1/namespaces/{}/endpointslices?labelSelector=kubernetes.io%2Fservice-name%3D{}",
+                self.config.namespace, self.config.service,
+            );
+
+            if let Some(lines) = &mut self.lines {
+                if let Some(line) = lines.try_next().await? {
+                    match serde_json::from_slice(&line)? {
+                        Event::Added(slice) | Event::Modified(slice) => {
+                            self.resource_version = slice.metadata.resource_version.clone();
+                            self.slices.insert(slice.metadata.name.clone(), slice);
+                        }
+                        Event::Deleted(slice) => {
+                            self.resource_version = slice.metadata.resource_version.clone();
+                            self.slices.remove(&slice.metadata.name);
+                        }
+                        Event::Bookmark(slice) => {
+                            self.resource_version = slice.metadata.resource_version;
+                        }
+                        Event::Error(status) => {
+                            self.resource_version = None;
+                            Err(WatchError(status))?
+                        }
+                    }
+                } else {
+                    self.lines = None;
+                }
+            } else if let Some(resource_version) = &self.resource_version {
+                let path = format!(
+                    "{path}&watch=1&allowWatchBookmarks=true&resourceVersion={resource_version}"
+                );
+                let response = self
+                    .api_server
+                    .send(http::Request::get(path).body(http_body_util::Empty::new())?)
+                    .await?;
+                let (parts, body) = response.into_parts();
+                if parts.status.is_success() {
+                    self.lines = Some(lines(body).boxed());
+                } else {
+                    self.resource_version = None;
+                    let body = body.collect().await?.to_bytes();
+                    Err(StatusError(http::Response::from_parts(parts, body)))?
+                }
+            } else {
+                let response = self
+                    .api_server
+                    .send(http::Request::get(path).body(http_body_util::Empty::new())?)
+                    .await?;
+                let (parts, body) = response.into_parts();
+                let body = body.collect().await?.to_bytes();
+                if parts.status.is_success() {
+                    let list = serde_json::from_slice::<EndpointSliceList>(&body)?;
+                    self.resource_version = list.metadata.resource_version;
+                    self.slices = list
+                        .items
+                        .into_iter()
+                        .map(|slice| (slice.metadata.name.clone(), slice))
+                        .collect();
+                } else {
+                    Err(StatusError(http::Response::from_parts(parts, body)))?
+                }
+            }
+            Ok(())
+        }
+
+        fn update(
+            &mut self,
+        ) -> Vec<(
+            client::Client,
+            endpoint::Metadata,
+            futures::future::AbortRegistration,
+        )> {
+            let mut item = Vec::new();
+            let mut abort_guards_next = HashMap::new();
+            for (addr, labels) in addrs(self.slices.values(), self.config.port.as_deref()) {
+                if let Some(abort_guard) = self.abort_guards.remove(&addr) {
+                    abort_guards_next.insert(addr, abort_guard);
+                } else {
+                    let config = client::standard::Config {
+                        uri: self.config.uri.clone(),
+                        http2_prior_knowledge: self.config.http2_prior_knowledge,
+                        resolve: Some(addr),
+                        unix_socket: None,
+                        authorization: self.config.authorization.clone(),
+                        tls: self.config.tls.clone(),
+                    };
+                    match client::Client::standard(config) {
+                        Ok(client) => {
+                            let (abort_guard, abort_registration) =
+                                misc::future::AbortGuard::new_pair();
+                            abort_guards_next.insert(addr, abort_guard);
+                            let metadata = endpoint::Metadata {
+                                labels,
+                                ..endpoint::Metadata::default()
+                            };
+                            item.push((client, metadata, abort_registration));
+                        }
+                        Err(e) => tracing::error!(error = e.to_string()),
+                    }
+                }
+            }
+            self.abort_guards = abort_guards_next;
+            item
+        }
+    }
+
+    let api_server = client::Client::standard(client::standard::Config {
+        uri: config.api_server.uri.clone(),
+        http2_prior_knowledge: false,
+        resolve: None,
+        unix_socket: None,
+        authorization: config.api_server.authorization.clone(),
+        tls: config.api_server.tls.clone(),
+    })?;
+    let state = State {
+        api_server,
+        config,
+        resource_version: None,
+        lines: None,
+        slices: HashMap::new(),
+        abort_guards: HashMap::new(),
+        sleep: None,
+    };
+    let stream = futures::stream::unfold(state, async |mut state| {
+        if let Some(sleep) = state.sleep.take() {
+            sleep.await;
+        }
+        match state.next().await {
+            Ok(()) => (),
+            Err(e) => {
+                tracing::warn!(error = e.to_string());
+                state.lines = None;
+                state.sleep = Some(tokio::time::sleep(state.config.retry_delay));
+            }
+        }
+        Some((state.update(), state))
+    });
+    Ok(stream)
+}
+
+fn lines(body: client::Body) -> impl futures::Stream<Item = Result<bytes::Bytes, Error>> + Send {
+    let stream = http_body_util::BodyDataStream::new(body);
+    futures::stream::try_unfold(
+        (stream, bytes::BytesMut::new()),
+        async |(mut stream, mut buffer)| {
+            loop {
+                if let Some(index) = buffer.iter().position(|b| *b == b'\n') {
+                    let line = buffer.split_to(index + 1).freeze();
+                    break Ok(Some((line, (stream, buffer))));
+                }
+                if let Some(data) = stream.try_next().await? {
+                    buffer.extend_from_slice(&data);
+                } else {
+                    break Ok(None);
+                }
+            }
+        },
+    )
+}
+
+fn addrs<'a, I>(slices: I, port: Option<&str>) -> BTreeMap<SocketAddr, BTreeMap<String, String>>
+where
+    I: IntoIterator<Item = &'a EndpointSlice>,
+{
+    let mut addrs = BTreeMap::new();
+    for slice in slices {
+        let ports = slice.ports.as_deref().unwrap_or_default();
+        let port = if let Some(port) = port {
+            ports.iter().find(|p| p.name.as_deref() == Some(port))
+        } else {
+            ports.first()
+        };
+        let Some(port) = port.and_then(|port| port.port) else {
+            continue;
+        };
+        for endpoint in slice.endpoints.as_deref().unwrap_or_default() {
+            let conditions = endpoint.conditions.as_ref();
+            let ready = conditions
+                .and_then(|conditions| conditions.ready)
+                .unwrap_or(true);
+            let terminating = conditions
+                .and_then(|conditions| conditions.terminating)
+                .unwrap_or(false);
+            if !ready || terminating {
+                continue;
+            }
+
+            let mut labels = BTreeMap::new();
+            if let Some(node_name) = &endpoint.node_name {
+                labels.insert("kubernetes.io/hostname".to_owned(), node_name.clone());
+            }
+            if let Some(zone) = &endpoint.zone {
+                labels.insert("topology.kubernetes.io/zone".to_owned(), zone.clone());
+            }
+            for address in &endpoint.addresses {
+                match address.parse::<IpAddr>() {
+                    Ok(ip) => {
+                        addrs.insert(SocketAddr::new(ip, port), labels.clone());
+                    }
+                    Err(e) => tracing::warn!(error = e.to_string()),
+                }
+            }
+        }
+    }
+    addrs
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", content = "object")]
+enum Event {
+    #[serde(rename = "ADDED")]
+    Added(EndpointSlice),
+    #[serde(rename = "MODIFIED")]
+    Modified(EndpointSlice),
+    #[serde(rename = "DELETED")]
+    Deleted(EndpointSlice),
+    #[serde(rename = "BOOKMARK")]
+    Bookmark(EndpointSlice),
+    #[serde(rename = "ERROR")]
+    Error(serde_json::Value),
+}
+
+#[derive(serde::Deserialize)]
+struct EndpointSliceList {
+    metadata: ObjectMeta,
+    items: Vec<EndpointSlice>,
+}
+
+#[derive(serde::Deserialize)]
+struct EndpointSlice {
+    metadata: ObjectMeta,
+    endpoints: Option<Vec<Endpoint>>,
+    ports: Option<Vec<EndpointPort>>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectMeta {
+    #[serde(default)]
+    name: String,
+    resource_version: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Endpoint {
+    addresses: Vec<String>,
+    conditions: Option<EndpointConditions>,
+    node_name: Option<String>,
+    zone: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct EndpointConditions {
+    ready: Option<bool>,
+    terminating: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+struct EndpointPort {
+    name: Option<String>,
+    port: Option<u16>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client;
+    use futures::StreamExt;
+    use std::collections::BTreeMap;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_watch() {
+        async fn endpointslices(
+            axum::extract::State(queries): axum::extract::State<Arc<Mutex<Vec<String>>>>,
+            axum::extract::RawQuery(query): axum::extract::RawQuery,
+        ) -> (http::StatusCode, axum::Json<serde_json::Value>) {
+            let query = query.unwrap_or_default();
+            let mut queries = queries.lock().unwrap();
+            queries.push(query.clone());
+            if query.contains("watch=1") {
+                let status = serde_json::json!({"kind": "Status", "code": 410});
+                (http::StatusCode::GONE, axum::Json(status))
+            } else {
+                let address = if queries.len() == 1 {
+                    "10.0.0.1"
+                } else {
+                    "10.0.0.2"
+                };
+                let list = serde_json::json!({
+                    "metadata": {"resourceVersion": "1"},
+                    "items": [{
+                        "metadata": {"name": "vllm-abcde"},
+                        "endpoints": [{"addresses": [address]}],
+                        "ports": [{"port": 8000}],
+                    }],
+                });
+                (http::StatusCode::OK, axum::Json(list))
+            }
+        }
+
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let app = axum::Router::new()
+            .route(
+                "/apis/discovery.k8s.io/v1/namespaces/default/endpointslices",
+                axum::routing::get(endpointslices),
+            )
+            .with_state(queries.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, app).into_future());
+
+        let config = serde_json::from_value::<super::Config>(serde_json::json!({
+            "api_server": {"uri": format!("http://{addr}")},
+            "namespace": "default",
+            "service": "vllm",
+            "uri": "http://vllm",
+            "retry_delay": "10ms",
+        }))
+        .unwrap();
+        let items = super::watch(config)
+            .unwrap()
+            .take(3)
+            .map(|item| {
+                item.into_iter()
+                    .map(|(client, _, _)| match client.config() {
+                        client::Config::Standard(config) => config.resolve,
+                        client::Config::Tunnel(_) => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            items,
+            [
+                vec![Some(SocketAddr::from(([10, 0, 0, 1], 8000)))],
+                Vec::new(),
+                vec![Some(SocketAddr::from(([10, 0, 0, 2], 8000)))],
+            ],
+        );
+
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries.len(), 3);
+        assert!(!queries[0].contains("watch=1"));
+        assert!(queries[1].contains("watch=1&allowWatchBookmarks=true&resourceVersion=1"));
+        assert!(!queries[2].contains("watch=1"));
+    }
+
+    #[test]
+    fn test_addrs() {
+        let slice = serde_json::from_value::<super::EndpointSlice>(serde_json::json!({
+            "metadata": {"name": "vllm-abcde", "resourceVersion": "42"},
+            "addressType": "IPv4",
+            "endpoints": [
+                {"addresses": ["10.0.0.1"], "conditions": {"ready": true}, "zone": "a"},
+                {"addresses": ["10.0.0.2"], "conditions": {"ready": false}},
+                {"addresses": ["10.0.0.3"], "conditions": {"ready": true, "terminating": true}},
+                {"addresses": ["10.0.0.4"]},
+            ],
+            "ports": [
+                {"name": "metrics", "port": 9090},
+                {"name": "http", "port": 8000},
+            ],
+        }))
+        .unwrap();
+
+        assert_eq!(
+            super::addrs([&slice], Some("http")),
+            BTreeMap::from([
+                (
+                    "10.0.0.1:8000".parse().unwrap(),
+                    BTreeMap::from([("topology.kubernetes.io/zone".to_owned(), "a".to_owned())]),
+                ),
+                ("10.0.0.4:8000".parse().unwrap(), BTreeMap::new()),
+            ]),
+        );
+        assert_eq!(
+            super::addrs([&slice], None).into_keys().collect::<Vec<_>>(),
+            [
+                SocketAddr::from(([10, 0, 0, 1], 9090)),
+                SocketAddr::from(([10, 0, 0, 4], 9090)),
+            ],
+        );
+        assert!(super::addrs([&slice], Some("grpc")).is_empty());
+    }
+}
@@ -1,35 +1,88 @@
-use crate::{Error, client, config};
+use crate::{Error, client, config, endpoint};
 use futures::StreamExt;
 use std::collections::HashMap;
 use std::future;
-use std::net::IpAddr;
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub(super) struct Config {
     #[serde(with = "http_serde::uri")]
+    #[schemars(with = "String")]
     uri: http::Uri,
     #[serde(default)]
     http2_prior_knowledge: bool,
     resolve: Option<Resolve>,
     unix_socket: Option<PathBuf>,
     authorization: Option<config::Authorization>,
+    tls: Option<config::Tls>,
 }
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct Resolve {
     #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
     interval: Duration,
+    #[serde(default)]
+    srv: bool,
+    ttl: Option<Ttl>,
+    #[serde(default, with = "humantime_serde")]
+    #[schemars(with = "Option<String>")]
+    grace_period: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Ttl {
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    min: Duration,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    max: Duration,
+}
+
+pub(super) fn check(config: &Config) -> Result<(), Error> {
+    if config.resolve.is_some() {
+        if config.unix_socket.is_some() {
+            Err("`resolve` and `unix_socket` do not work together")?
+        }
+        if config.uri.host().is_none() {
+            Err("missing host")?
+        }
+    }
+    if let Some(resolve) = &config.resolve
+        && resolve.srv
+        && config.uri.port().is_some()
+    {
+        Err("`uri` must not have a port with `srv`")?
+    }
+    if let Some(resolve) = &config.resolve
+        && let Some(ttl) = &resolve.ttl
+        && ttl.min > ttl.max
+    {
+        Err("`ttl.min` must not exceed `ttl.max`")?
+    }
+    if let Some(authorization) = &config.authorization {
+        authorization.check()?;
+    }
+    Ok(())
 }
 
 pub(super) fn watch(
     resolver: hickory_resolver::TokioResolver,
     mut config: Config,
 ) -> Result<
-    impl futures::Stream<Item = Vec<(client::Client, futures::future::AbortRegistration)>> + Send,
+    impl futures::Stream<
+        Item = Vec<(
+            client::Client,
+            endpoint::Metadata,
+            futures::future::AbortRegistration,
+        )>,
+    > + Send,
     Error,
 > {
     let stream = if config.unix_socket.is_none()
@@ -43,10 +96,16 @@ pub(super) fn watch(
             resolve: None,
             unix_socket: config.unix_socket,
             authorization: config.authorization,
+            tls: config.tls,
         };
         let client = client::Client::standard(config)?;
         let (_, abort_registration) = futures::future::AbortHandle::new_pair();
-        futures::stream::once(future::ready(vec![(client, abort_registration)])).left_stream()
+        futures::stream::once(future::ready(vec![(
+            client,
+            endpoint::Metadata::default(),
+            abort_registration,
+        )]))
+        .left_stream()
     };
     Ok(stream)
 }
@@ -56,7 +115,13 @@ fn watch_resolve(
     config: Config,
     resolve: Resolve,
 ) -> Result<
-    impl futures::Stream<Item = Vec<(client::Client, futures::future::AbortRegistration)>> + Send,
+    impl futures::Stream<
+        Item = Vec<(
+            client::Client,
+            endpoint::Metadata,
+            futures::future::AbortRegistration,
+        )>,
+    > + Send,
     Error,
 > {
     struct State {
@@ -64,49 +129,152 @@ fn watch_resolve(
         config: Config,
         interval: misc::time::Interval,
         host: String,
-        abort_guards: HashMap<IpAddr, misc::future::AbortGuard>,
+        resolve: Resolve,
+        deadline: tokio::time::Instant,
+        abort_guards: HashMap<
+            (http::Uri, SocketAddr),
+            (
+                endpoint::Metadata,
+                misc::future::AbortGuard,
+                Option<tokio::time::Instant>,
+            ),
+        >,
     }
 
     impl State {
-        async fn next(&mut self) -> Vec<(client::Client, futures::future::AbortRegistration)> {
-            self.interval.tick().await;
+        async fn next(
+            &mut self,
+        ) -> Vec<(
+            client::Client,
+            endpoint::Metadata,
+            futures::future::AbortRegistration,
+        )> {
+            if self.resolve.ttl.is_some() {
+                tokio::time::sleep_until(self.deadline).await;
+            } else {
+                self.interval.tick().await;
+            }
             let mut item = Vec::new();
-            match self.resolver.lookup_ip(&self.host).await {
-                Ok(lookup_ip) => {
-                    let mut abort_guards_next = HashMap::new();
-                    for ip_addr in lookup_ip.iter() {
-                        if let Some(abort_guard) = self.abort_guards.remove(&ip_addr) {
-                            abort_guards_next.insert(ip_addr, abort_guard);
-                        } else {
-                            let config = client::standard::Config {
-                                uri: self.config.uri.clone(),
-                                http2_prior_knowledge: self.config.http2_prior_knowledge,
-                                resolve: Some((ip_addr, 0).into()),
-                                unix_socket: None,
-                                authorization: self.config.authorization.clone(),
-                            };
-                            match client::Client::standard(config) {
-                                Ok(client) => {
-                                    let (abort_guard, abort_registration) =
-                                        misc::future::AbortGuard::new_pair();
-                                    abort_guards_next.insert(ip_addr, abort_guard);
-                                    item.push((client, abort_registration));
-                                }
-                                Err(e) => tracing::error!(error = e.to_string()),
+            let lookup = self.lookup().await;
+            let now = tokio::time::Instant::now();
+            self.deadline = now + self.resolve.interval;
+            if let Some((targets, valid_until)) = lookup {
+                if let Some(ttl) = &self.resolve.ttl
+                    && let Some(valid_until) = valid_until
+                {
+                    self.deadline = now
+                        + valid_until
+                            .saturating_duration_since(Instant::now())
+                            .clamp(ttl.min, ttl.max);
+                }
+
+                let mut abort_guards_next = HashMap::new();
+                for (uri, addr, metadata) in targets {
+                    let key = (uri, addr);
+                    if abort_guards_next.contains_key(&key) {
+                        continue;
+                    }
+                    if let Some((running, abort_guard, _)) = self.abort_guards.remove(&key)
+                        && running == metadata
+                    {
+                        abort_guards_next.insert(key, (metadata, abort_guard, None));
+                    } else {
+                        let (uri, addr) = &key;
+                        let config = client::standard::Config {
+                            uri: uri.clone(),
+                            http2_prior_knowledge: self.config.http2_prior_knowledge,
+                            resolve: Some(*addr),
+                            unix_socket: None,
+                            authorization: self.config.authorization.clone(),
+                            tls: self.config.tls.clone(),
+                        };
+                        match client::Client::standard(config) {
+                            Ok(client) => {
+                                let (abort_guard, abort_registration) =
+                                    misc::future::AbortGuard::new_pair();
+                                item.push((client, metadata.clone(), abort_registration));
+                                abort_guards_next.insert(key, (metadata, abort_guard, None));
                             }
+                            Err(e) => tracing::error!(error = e.to_string()),
                         }
                     }
-                    self.abort_guards = abort_guards_next;
                 }
-                Err(e) => {
-                    tracing::warn!(error = e.to_string());
-                    if e.is_nx_domain() || e.is_no_records_found() {
-                        self.abort_guards.clear()
+                for (key, (metadata, abort_guard, vanished_at)) in self.abort_guards.drain() {
+                    let vanished_at = vanished_at.unwrap_or(now);
+                    if now.duration_since(vanished_at) < self.resolve.grace_period {
+                        abort_guards_next.insert(key, (metadata, abort_guard, Some(vanished_at)));
                     }
                 }
+                self.abort_guards = abort_guards_next;
             }
             item
         }
+
+        async fn lookup(
+            &self,
+        ) -> Option<(
+            Vec<(http::Uri, SocketAddr, endpoint::Metadata)>,
+            Option<Instant>,
+        )> {
+            let mut valid_until = None::<Instant>;
+            let targets = if self.resolve.srv {
+                match self.resolver.srv_lookup(&self.host).await {
+                    Ok(srv_lookup) => {
+                        valid_until = Some(srv_lookup.as_lookup().valid_until());
+                        srv_lookup
+                            .iter()
+                            .filter(|srv| !srv.target().is_root())
+                            .map(|srv| {
+                                let metadata = endpoint::Metadata {
+                                    priority: Some(srv.priority()),
+                                    weight: srv.weight(),
+                                    ..endpoint::Metadata::default()
+                                };
+                                (srv.target().to_utf8(), srv.port(), metadata)
+                            })
+                            .collect()
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = e.to_string());
+                        return (e.is_nx_domain() || e.is_no_records_found())
+                            .then(|| (Vec::new(), None));
+                    }
+                }
+            } else {
+                vec![(self.host.clone(), 0, endpoint::Metadata::default())]
+            };
+
+            let mut addrs = Vec::new();
+            for (host, port, metadata) in targets {
+                let mut uri = self.config.uri.clone();
+                if self.resolve.srv
+                    && let Err(e) = client::set_host(&mut uri, host.trim_end_matches('.'))
+                {
+                    tracing::warn!(error = e.to_string());
+                    continue;
+                }
+                match self.resolver.lookup_ip(&host).await {
+                    Ok(lookup_ip) => {
+                        let v = lookup_ip.valid_until();
+                        valid_until = Some(valid_until.map_or(v, |valid_until| valid_until.min(v)));
+                        addrs.extend(lookup_ip.iter().map(|ip_addr| {
+                            (
+                                uri.clone(),
+                                SocketAddr::new(ip_addr, port),
+                                metadata.clone(),
+                            )
+                        }));
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = e.to_string());
+                        if !(e.is_nx_domain() || e.is_no_records_found()) {
+                            return None;
+                        }
+                    }
+                }
+            }
+            Some((addrs, valid_until))
+        }
     }
 
     let host = config.uri.host().ok_or("missing host")?.to_owned();
@@ -115,6 +283,8 @@ fn watch_resolve(
         config,
         host,
         interval: misc::time::interval(resolve.interval),
+        deadline: tokio::time::Instant::now(),
+        resolve,
         abort_guards: HashMap::new(),
     };
     let stream =
@@ -1,34 +1,219 @@
-use crate::{Error, client, config};
-use futures::{StreamExt, TryFutureExt};
+use crate::{Error, client, config, endpoint, tls};
+use futures::{FutureExt, StreamExt, TryFutureExt};
+use std::collections::BTreeMap;
 use std::future;
+use std::sync::Arc;
 use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::handshake::server;
 use tracing::Instrument;
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub(super) struct Config {
     bind: config::Bind,
     #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
     keep_alive_interval: Duration,
+    #[serde(default)]
+    authentication: Vec<Authentication>,
 }
 
-pub(super) async fn watch(
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+enum Authentication {
+    #[serde(rename = "authorization")]
+    Authorization {
+        identity: String,
+        authorization: config::Authorization,
+    },
+    #[serde(rename = "tls")]
+    Tls { identity: Option<String> },
+}
+
+const IDENTITY: &str = "identity";
+const MAX_HANDSHAKES: usize = 1024;
+const BIND_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+pub(super) fn check(config: &Config) -> Result<(), Error> {
+    config.bind.check()?;
+    let client_auth = matches!(
+        &config.bind,
+        config::Bind::Tls(tls) if !tls.client_ca_certificates.is_empty()
+    );
+    if !client_auth
+        && config
+            .authentication
+            .iter()
+            .any(|authentication| matches!(authentication, Authentication::Tls { .. }))
+    {
+        Err("`tls` authentication requires `client_ca_certificates`")?
+    }
+    for authentication in &config.authentication {
+        if let Authentication::Authorization { authorization, .. } = authentication {
+            authorization.check()?;
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn watch(
     config: Config,
-) -> Result<
-    impl futures::Stream<Item = Vec<(client::Client, futures::future::AbortRegistration)>> + Send,
-    Error,
-> {
+) -> impl futures::Stream<
+    Item = Vec<(
+        client::Client,
+        endpoint::Metadata,
+        futures::future::AbortRegistration,
+    )>,
+> + Send {
+    type Item = (
+        client::Client,
+        endpoint::Metadata,
+        futures::future::AbortRegistration,
+    );
+
     struct State {
-        listener: tokio_net_incoming::Listener,
+        config: Config,
+        listener: Option<(tokio_net_incoming::Listener, Arc<Handshake>)>,
+        handshakes: futures::stream::FuturesUnordered<
+            futures::future::BoxFuture<'static, Result<Item, Error>>,
+        >,
+        sleep: Option<tokio::time::Sleep>,
+    }
+
+    struct Handshake {
+        acceptor: Option<tokio_rustls::TlsAcceptor>,
         keep_alive_interval: Duration,
+        authentication: Vec<Authentication>,
     }
 
     impl State {
-        async fn accept(
+        async fn next(&mut self) -> Result<Item, Error> {
+            if let Some(sleep) = self.sleep.take() {
+                sleep.await;
+            }
+            let (listener, handshake) = if let Some(listener) = &mut self.listener {
+                listener
+            } else {
+                match self.config.bind.clone().bind().await {
+                    Ok((listener, acceptor)) => {
+                        let handshake = Handshake {
+                            acceptor,
+                            keep_alive_interval: self.config.keep_alive_interval,
+                            authentication: self.config.authentication.clone(),
+                        };
+                        self.listener.insert((listener, Arc::new(handshake)))
+                    }
+                    Err(e) => {
+                        self.sleep = Some(tokio::time::sleep(BIND_RETRY_DELAY));
+                        return Err(e);
+                    }
+                }
+            };
+            loop {
+                tokio::select! {
+                    stream = listener.accept(), if self.handshakes.len() < MAX_HANDSHAKES => {
+                        let (stream, _) = stream?;
+                        let handshake = handshake.clone();
+                        self.handshakes.push(
+                            async move {
+                                tokio::time::timeout(
+                                    tls::HANDSHAKE_TIMEOUT,
+                                    handshake.accept(stream),
+                                )
+                                .await?
+                            }
+                            .in_current_span()
+                            .boxed(),
+                        );
+                    }
+                    Some(item) = self.handshakes.next() => break item,
+                }
+            }
+        }
+    }
+
+    impl Handshake {
+        async fn accept<S>(&self, stream: S) -> Result<Item, Error>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+            misc::tungstenite::Io<tokio_tungstenite::WebSocketStream<S>>:
+                hyper::rt::Read + hyper::rt::Write + Send + Sync + Unpin + 'static,
+            misc::tungstenite::Io<tokio_tungstenite::WebSocketStream<tls::TlsStream<S>>>:
+                hyper::rt::Read + hyper::rt::Write + Send + Sync + Unpin + 'static,
+        {
+            if let Some(acceptor) = &self.acceptor {
+                let stream = tls::accept(acceptor, stream).await?;
+                let peer_identity = stream.peer_identity();
+                self.handshake(stream, peer_identity).await
+            } else {
+                self.handshake(stream, None).await
+            }
+        }
+
+        async fn handshake<S>(
             &self,
-        ) -> Result<(client::Client, futures::future::AbortRegistration), Error> {
-            let (stream, _) = self.listener.accept().await?;
-            let stream = tokio_tungstenite::accept_async(stream).await?;
+            stream: S,
+            peer_identity: Option<String>,
+        ) -> Result<Item, Error>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+            misc::tungstenite::Io<tokio_tungstenite::WebSocketStream<S>>:
+                hyper::rt::Read + hyper::rt::Write + Send + Sync + Unpin + 'static,
+        {
+            let mut candidates = Vec::new();
+            for authentication in &self.authentication {
+                match authentication {
+                    Authentication::Authorization {
+                        identity,
+                        authorization,
+                    } => match authorization.header().await {
+                        Ok(header) => candidates.push((identity.clone(), Some(header))),
+                        Err(e) => {
+                            tracing::warn!(identity = identity.as_str(), error = e.to_string())
+                        }
+                    },
+                    Authentication::Tls { identity } => {
+                        if let Some(peer_identity) = &peer_identity
+                            && identity
+                                .as_ref()
+                                .is_none_or(|identity| identity == peer_identity)
+                        {
+                            candidates.push((peer_identity.clone(), None));
+                        }
+                    }
+                }
+            }
+
+            let unauthenticated = self.authentication.is_empty();
+            let mut identity = None;
+            let callback = |request: &server::Request, response: server::Response| {
+                identity = candidates
+                    .into_iter()
+                    .find_map(|(identity, header)| match header {
+                        Some((name, value)) => request
+                            .headers()
+                            .get(name)
+                            .is_some_and(|v| v.as_bytes().ct_eq(value.as_bytes()).into())
+                            .then_some(identity),
+                        None => Some(identity),
+                    });
+                if unauthenticated || identity.is_some() {
+                    Ok(response)
+                } else {
+                    let mut response = server::ErrorResponse::new(None);
+                    *response.status_mut() = http::StatusCode::UNAUTHORIZED;
+                    Err(response)
+                }
+            };
+            let stream = tokio_tungstenite::accept_hdr_async(stream, callback).await?;
+            let metadata = endpoint::Metadata {
+                labels: identity
+                    .map(|identity| BTreeMap::from([(IDENTITY.to_owned(), identity)]))
+                    .unwrap_or_default(),
+                ..endpoint::Metadata::default()
+            };
             let (client, connection) = client::Client::tunnel(
                 stream,
                 client::tunnel::Config {
@@ -45,18 +230,22 @@ pub(super) async fn watch(
                 .inspect_err(|e| tracing::warn!(error = e.to_string()))
                 .instrument(tracing::Span::current()),
             );
-            Ok((client, abort_registration))
+            Ok((client, metadata, abort_registration))
         }
     }
 
-    let listener = config.bind.bind().await?;
+    if config.authentication.is_empty() {
+        tracing::warn!("tunnel accepts connections without `authentication`");
+    }
     let state = State {
-        listener,
-        keep_alive_interval: config.keep_alive_interval,
+        config,
+        listener: None,
+        handshakes: futures::stream::FuturesUnordered::new(),
+        sleep: None,
     };
-    let stream = futures::stream::unfold(state, async |state| {
+    let stream = futures::stream::unfold(state, async |mut state| {
         loop {
-            match state.accept().await {
+            match state.next().await {
                 Ok(item) => {
                     break Some((item, state));
                 }
@@ -66,5 +255,110 @@ pub(super) async fn watch(
             }
         }
     });
-    Ok(futures::stream::once(future::ready(Vec::new())).chain(stream.map(|item| vec![item])))
+    futures::stream::once(future::ready(Vec::new())).chain(stream.map(|item| vec![item]))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite;
+
+    #[tokio::test]
+    async fn test_watch_authentication() {
+        let path = std::env::temp_dir().join(format!("haori-{}.sock", uuid::Uuid::new_v4()));
+        let config = serde_json::from_value::<super::Config>(serde_json::json!({
+            "bind": {"unix": path},
+            "keep_alive_interval": "10s",
+            "authentication": [
+                {"authorization": {
+                    "identity": "bob",
+                    "authorization": {"bearer": {"token": {"env": "HAORI_TEST_UNDEFINED"}}},
+                }},
+                {"authorization": {
+                    "identity": "alice",
+                    "authorization": {"bearer": {"token": {"inline": "secret"}}},
+                }},
+            ],
+        }))
+        .unwrap();
+        let mut stream = Box::pin(super::watch(config));
+        assert!(stream.next().await.unwrap().is_empty());
+
+        let connect = async |authorization: Option<&str>| {
+            let mut request =
+                tungstenite::ClientRequestBuilder::new("ws://localhost/".parse().unwrap());
+            if let Some(authorization) = authorization {
+                request = request.with_header("authorization", authorization);
+            }
+            let stream = loop {
+                if let Ok(stream) = tokio::net::UnixStream::connect(&path).await {
+                    break stream;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            };
+            tokio_tungstenite::client_async(request, stream).await
+        };
+
+        for authorization in [None, Some("Bearer wrong"), Some("Bearer ")] {
+            tokio::select! {
+                result = connect(authorization) => assert!(matches!(
+                    result,
+                    Err(tungstenite::Error::Http(response))
+                        if response.status() == http::StatusCode::UNAUTHORIZED
+                )),
+                _ = stream.next() => unreachable!(),
+            }
+        }
+
+        let (item, result) = futures::join!(stream.next(), connect(Some("Bearer secret")));
+        assert!(result.is_ok());
+        let item = item.unwrap();
+        assert_eq!(item.len(), 1);
+        assert_eq!(
+            item[0].1.labels,
+            BTreeMap::from([(super::IDENTITY.to_owned(), "alice".to_owned())]),
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_watch_rebind() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = |keep_alive_interval: &str| {
+            serde_json::from_value::<super::Config>(serde_json::json!({
+                "bind": {"tcp": addr},
+                "keep_alive_interval": keep_alive_interval,
+            }))
+            .unwrap()
+        };
+        let connect = async || loop {
+            if let Ok(stream) = tokio::net::TcpStream::connect(addr).await {
+                break tokio_tungstenite::client_async("ws://localhost/", stream).await;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        let mut running = Box::pin(super::watch(config("10s")));
+        assert!(running.next().await.unwrap().is_empty());
+        let (item, result) = futures::join!(running.next(), connect());
+        assert_eq!(item.unwrap().len(), 1);
+        assert!(result.is_ok());
+
+        let mut reloaded = Box::pin(super::watch(config("20s")));
+        assert!(reloaded.next().await.unwrap().is_empty());
+        let next = reloaded.next();
+        let (item, result) = futures::join!(next, async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            drop(running);
+            connect().await
+        });
+        assert_eq!(item.unwrap().len(), 1);
+        assert!(result.is_ok());
+    }
 }
@@ -1,20 +1,54 @@
+mod llamacpp;
 mod native;
+mod ollama;
+mod openmetrics;
+mod sglang;
+mod r#static;
+mod tgi;
 mod vllm;
 
 use crate::{Error, client};
-use futures::StreamExt;
+use futures::{StreamExt, TryFutureExt};
+use http_body_util::BodyExt;
+use std::collections::BTreeMap;
+use std::time::Duration;
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub(super) struct Config(Inner);
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 enum Inner {
     #[serde(rename = "native")]
     Native(native::Config),
     #[serde(rename = "vllm")]
     Vllm(vllm::Config),
+    #[serde(rename = "sglang")]
+    Sglang(sglang::Config),
+    #[serde(rename = "tgi")]
+    Tgi(tgi::Config),
+    #[serde(rename = "ollama")]
+    Ollama(ollama::Config),
+    #[serde(rename = "llamacpp")]
+    Llamacpp(llamacpp::Config),
+    #[serde(rename = "static")]
+    Static(r#static::Config),
+    #[serde(rename = "openmetrics")]
+    Openmetrics(openmetrics::Config),
+}
+
+pub(super) fn check(config: &Config) -> Result<(), Error> {
+    match &config.0 {
+        Inner::Native(_)
+        | Inner::Vllm(_)
+        | Inner::Sglang(_)
+        | Inner::Tgi(_)
+        | Inner::Ollama(_)
+        | Inner::Llamacpp(_)
+        | Inner::Static(_) => Ok(()),
+        Inner::Openmetrics(config) => openmetrics::check(config),
+    }
 }
 
 pub(super) fn watch(
@@ -22,7 +56,127 @@ pub(super) fn watch(
     config: Config,
 ) -> impl futures::Stream<Item = Result<Vec<schemas::Provider>, Error>> + Send {
     match config.0 {
-        Inner::Native(config) => native::watch(client, config).left_stream(),
-        Inner::Vllm(config) => vllm::watch(client, config).right_stream(),
+        Inner::Native(config) => native::watch(client, config)
+            .left_stream()
+            .left_stream()
+            .left_stream(),
+        Inner::Vllm(config) => vllm::watch(client, config)
+            .right_stream()
+            .left_stream()
+            .left_stream(),
+        Inner::Sglang(config) => sglang::watch(client, config)
+            .left_stream()
+            .right_stream()
+            .left_stream(),
+        Inner::Tgi(config) => tgi::watch(client, config)
+            .right_stream()
+            .right_stream()
+            .left_stream(),
+        Inner::Ollama(config) => ollama::watch(client, config)
+            .left_stream()
+            .left_stream()
+            .right_stream(),
+        Inner::Llamacpp(config) => llamacpp::watch(client, config)
+            .right_stream()
+            .left_stream()
+            .right_stream(),
+        Inner::Static(config) => r#static::watch(client, config)
+            .left_stream()
+            .right_stream()
+            .right_stream(),
+        Inner::Openmetrics(config) => openmetrics::watch(client, config)
+            .right_stream()
+            .right_stream()
+            .right_stream(),
+    }
+}
+
+async fn get(client: &client::Client, uri: &str) -> Result<http::Response<bytes::Bytes>, Error> {
+    #[derive(Debug, thiserror::Error)]
+    #[error("{0:?}")]
+    struct StatusError(http::Response<bytes::Bytes>);
+
+    let response = client
+        .send(http::Request::get(uri).body(http_body_util::Empty::new())?)
+        .await?;
+    let (parts, body) = response.into_parts();
+    let body = body
+        .collect()
+        .map_ok(http_body_util::Collected::to_bytes)
+        .await?;
+    let response = http::Response::from_parts(parts, body);
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(StatusError(response).into())
     }
 }
+
+#[tracing::instrument(err(level = tracing::Level::WARN), skip_all)]
+async fn list_models(client: &client::Client) -> Result<Vec<schemas::Model>, Error> {
+    let response = get(client, "/v1/models").await?;
+    let body = serde_json::from_slice::<schemas::List<_>>(response.body())?;
+    Ok(body.data)
+}
+
+trait Probe {
+    const PROTOCOL: Option<&'static str> = None;
+
+    fn probe(
+        &mut self,
+        client: &client::Client,
+    ) -> impl Future<Output = Result<(Vec<schemas::Model>, schemas::Metrics), Error>> + Send;
+}
+
+fn poll<P>(
+    client: client::Client,
+    interval: Duration,
+    timeout: Duration,
+    probe: P,
+) -> impl futures::Stream<Item = Result<Vec<schemas::Provider>, Error>> + Send
+where
+    P: Probe + Send,
+{
+    struct State<P> {
+        client: client::Client,
+        id: uuid::Uuid,
+        interval: misc::time::Interval,
+        timeout: Duration,
+        probe: P,
+    }
+
+    impl<P> State<P>
+    where
+        P: Probe,
+    {
+        async fn next(&mut self) -> Result<schemas::Provider, Error> {
+            self.interval.tick().await;
+            let future = self.probe.probe(&self.client);
+            let (models, metrics) = tokio::time::timeout(self.timeout, future).await??;
+            let provider = schemas::Provider {
+                id: self.id,
+                models,
+                metrics,
+                labels: BTreeMap::new(),
+                protocol: P::PROTOCOL.map(str::to_owned),
+            };
+            Ok(provider)
+        }
+    }
+
+    let state = State {
+        client,
+        id: uuid::Uuid::new_v4(),
+        interval: misc::time::interval(interval),
+        timeout,
+        probe,
+    };
+    futures::stream::unfold(state, async |mut state| {
+        let item = match state.next().await {
+            Ok(provider) => Some(Ok(vec![provider])),
+            Err(e) if client::is_closed(&e) => None,
+            Err(e) => Some(Err(e)),
+        };
+        Some((item?, state))
+    })
+}
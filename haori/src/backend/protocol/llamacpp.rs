@@ -0,0 +1,63 @@
+use crate::{Error, client};
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Config {
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    interval: Duration,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    timeout: Duration,
+}
+
+pub(super) fn watch(
+    client: client::Client,
+    config: Config,
+) -> impl futures::Stream<Item = Result<Vec<schemas::Provider>, Error>> + Send {
+    super::poll(client, config.interval, config.timeout, State)
+}
+
+struct State;
+
+impl super::Probe for State {
+    async fn probe(
+        &mut self,
+        client: &client::Client,
+    ) -> Result<(Vec<schemas::Model>, schemas::Metrics), Error> {
+        let (models, slots) =
+            futures::future::try_join(super::list_models(client), list_slots(client)).await?;
+        Ok((models, metrics(&slots)?))
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Slot {
+    is_processing: Option<bool>,
+    state: Option<u32>,
+}
+
+impl Slot {
+    fn is_processing(&self) -> bool {
+        self.is_processing
+            .or(self.state.map(|state| state != 0))
+            .unwrap_or_default()
+    }
+}
+
+fn metrics(slots: &[Slot]) -> Result<schemas::Metrics, Error> {
+    let running = slots.iter().filter(|slot| slot.is_processing()).count();
+    let waiting = usize::from(!slots.is_empty() && running == slots.len());
+    Ok(schemas::Metrics {
+        vllm_num_requests_running: Some(running.try_into()?),
+        vllm_num_requests_waiting: Some(waiting.try_into()?),
+        ..schemas::Metrics::default()
+    })
+}
+
+#[tracing::instrument(err(level = tracing::Level::WARN), skip_all)]
+async fn list_slots(client: &client::Client) -> Result<Vec<Slot>, Error> {
+    let response = super::get(client, "/slots").await?;
+    Ok(serde_json::from_slice(response.body())?)
+}
@@ -5,12 +5,13 @@ use std::future;
 use std::pin::Pin;
 use std::time::Duration;
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields, tag = "version")]
 pub(super) enum Config {
     #[serde(rename = "1")]
     V1 {
         #[serde(with = "humantime_serde")]
+        #[schemars(with = "String")]
         retry_delay: Duration,
     },
 }
@@ -0,0 +1,62 @@
+use crate::{Error, client};
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Config {
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    interval: Duration,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    timeout: Duration,
+}
+
+pub(super) fn watch(
+    client: client::Client,
+    config: Config,
+) -> impl futures::Stream<Item = Result<Vec<schemas::Provider>, Error>> + Send {
+    super::poll(client, config.interval, config.timeout, State)
+}
+
+struct State;
+
+impl super::Probe for State {
+    async fn probe(
+        &mut self,
+        client: &client::Client,
+    ) -> Result<(Vec<schemas::Model>, schemas::Metrics), Error> {
+        let (tags, ps) = futures::future::try_join(
+            list_models(client, "/api/tags"),
+            list_models(client, "/api/ps"),
+        )
+        .await?;
+        let models = tags
+            .into_iter()
+            .map(|model| {
+                let loaded = ps.iter().any(|ps| ps.name == model.name);
+                let mut model = schemas::Model::new(model.name);
+                model.loaded = Some(loaded);
+                model
+            })
+            .collect();
+        Ok((models, schemas::Metrics::default()))
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Models {
+    models: Vec<Model>,
+}
+
+#[derive(serde::Deserialize)]
+struct Model {
+    name: String,
+}
+
+#[tracing::instrument(err(level = tracing::Level::WARN), skip(client))]
+async fn list_models(client: &client::Client, uri: &str) -> Result<Vec<Model>, Error> {
+    let response = super::get(client, uri).await?;
+    let body = serde_json::from_slice::<Models>(response.body())?;
+    Ok(body.models)
+}
@@ -0,0 +1,189 @@
+use crate::{Error, client};
+use std::collections::BTreeMap;
+use std::ops::AddAssign;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Config {
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    interval: Duration,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    timeout: Duration,
+    signals: Vec<Signal>,
+    model_label: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Signal {
+    name: SignalName,
+    metric: String,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    aggregation: Aggregation,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+enum SignalName {
+    #[serde(rename = "num_requests_running")]
+    NumRequestsRunning,
+    #[serde(rename = "num_requests_waiting")]
+    NumRequestsWaiting,
+    #[serde(rename = "kv_cache_usage_perc")]
+    KvCacheUsagePerc,
+    #[serde(rename = "time_to_first_token_seconds_p50")]
+    TimeToFirstTokenSecondsP50,
+    #[serde(rename = "time_to_first_token_seconds_p99")]
+    TimeToFirstTokenSecondsP99,
+    #[serde(rename = "time_per_output_token_seconds_p50")]
+    TimePerOutputTokenSecondsP50,
+    #[serde(rename = "time_per_output_token_seconds_p99")]
+    TimePerOutputTokenSecondsP99,
+}
+
+impl SignalName {
+    fn is_count(self) -> bool {
+        matches!(self, Self::NumRequestsRunning | Self::NumRequestsWaiting)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+enum Aggregation {
+    #[default]
+    #[serde(rename = "sum")]
+    Sum,
+    #[serde(rename = "max")]
+    Max,
+    #[serde(rename = "avg")]
+    Avg,
+    #[serde(rename = "quantile")]
+    Quantile(f64),
+}
+
+pub(super) fn check(config: &Config) -> Result<(), Error> {
+    for signal in &config.signals {
+        if signal.name.is_count() && matches!(signal.aggregation, Aggregation::Quantile(_)) {
+            Err(format!(
+                "`quantile` cannot be used for the request count signal from `{}`",
+                signal.metric,
+            ))?
+        }
+        if let Aggregation::Quantile(q) = signal.aggregation
+            && !(0. ..=1.).contains(&q)
+        {
+            Err(format!(
+                "`quantile` for `{}` must be between 0 and 1, got {q}",
+                signal.metric,
+            ))?
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn watch(
+    client: client::Client,
+    config: Config,
+) -> impl futures::Stream<Item = Result<Vec<schemas::Provider>, Error>> + Send {
+    let state = State {
+        signals: config.signals,
+        model_label: config.model_label,
+        histograms: misc::metrics::Histograms::default(),
+    };
+    super::poll(client, config.interval, config.timeout, state)
+}
+
+struct State {
+    signals: Vec<Signal>,
+    model_label: Option<String>,
+    histograms: misc::metrics::Histograms,
+}
+
+impl super::Probe for State {
+    async fn probe(
+        &mut self,
+        client: &client::Client,
+    ) -> Result<(Vec<schemas::Model>, schemas::Metrics), Error> {
+        futures::future::try_join(
+            super::list_models(client),
+            scrape_metrics(
+                client,
+                &self.signals,
+                self.model_label.as_deref(),
+                &mut self.histograms,
+            ),
+        )
+        .await
+    }
+}
+
+#[tracing::instrument(err(level = tracing::Level::WARN), skip_all)]
+async fn scrape_metrics(
+    client: &client::Client,
+    signals: &[Signal],
+    model_label: Option<&str>,
+    histograms: &mut misc::metrics::Histograms,
+) -> Result<schemas::Metrics, Error> {
+    let response = super::get(client, "/metrics").await?;
+    let mappings = signals
+        .iter()
+        .map(|signal| misc::metrics::Mapping {
+            metricname: &signal.metric,
+            labels: &signal.labels,
+            aggregation: match signal.aggregation {
+                Aggregation::Sum => misc::metrics::Aggregation::Sum,
+                Aggregation::Max => misc::metrics::Aggregation::Max,
+                Aggregation::Avg => misc::metrics::Aggregation::Avg,
+                Aggregation::Quantile(q) => misc::metrics::Aggregation::Quantile(q),
+            },
+            group_by: model_label,
+        })
+        .collect::<Vec<_>>();
+    let aggregated =
+        misc::metrics::aggregate(str::from_utf8(response.body())?, &mappings, histograms)?;
+
+    let mut metrics = schemas::Metrics::default();
+    for (signal, aggregated) in signals.iter().zip(aggregated) {
+        if let Some(value) = aggregated.value {
+            add(&mut metrics, signal.name, value);
+        }
+        for (model, value) in aggregated.groups {
+            add(metrics.models.entry(model).or_default(), signal.name, value);
+        }
+    }
+    Ok(metrics)
+}
+
+fn add(metrics: &mut schemas::Metrics, name: SignalName, value: f64) {
+    fn add_to<T>(field: &mut Option<T>, value: T)
+    where
+        T: AddAssign + Default,
+    {
+        *field.get_or_insert_default() += value;
+    }
+
+    match name {
+        SignalName::NumRequestsRunning => {
+            add_to(&mut metrics.vllm_num_requests_running, value.round() as u32);
+        }
+        SignalName::NumRequestsWaiting => {
+            add_to(&mut metrics.vllm_num_requests_waiting, value.round() as u32);
+        }
+        SignalName::KvCacheUsagePerc => add_to(&mut metrics.vllm_kv_cache_usage_perc, value),
+        SignalName::TimeToFirstTokenSecondsP50 => {
+            add_to(&mut metrics.vllm_time_to_first_token_seconds_p50, value);
+        }
+        SignalName::TimeToFirstTokenSecondsP99 => {
+            add_to(&mut metrics.vllm_time_to_first_token_seconds_p99, value);
+        }
+        SignalName::TimePerOutputTokenSecondsP50 => {
+            add_to(&mut metrics.vllm_time_per_output_token_seconds_p50, value);
+        }
+        SignalName::TimePerOutputTokenSecondsP99 => {
+            add_to(&mut metrics.vllm_time_per_output_token_seconds_p99, value);
+        }
+    }
+}
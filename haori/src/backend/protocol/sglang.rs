@@ -0,0 +1,64 @@
+use crate::{Error, client};
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Config {
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    interval: Duration,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    timeout: Duration,
+}
+
+pub(super) fn watch(
+    client: client::Client,
+    config: Config,
+) -> impl futures::Stream<Item = Result<Vec<schemas::Provider>, Error>> + Send {
+    super::poll(client, config.interval, config.timeout, State)
+}
+
+struct State;
+
+impl super::Probe for State {
+    async fn probe(
+        &mut self,
+        client: &client::Client,
+    ) -> Result<(Vec<schemas::Model>, schemas::Metrics), Error> {
+        let (models_metrics, server_info) = futures::future::join(
+            futures::future::try_join(super::list_models(client), scrape_metrics(client)),
+            get_server_info(client),
+        )
+        .await;
+        let (mut models, metrics) = models_metrics?;
+        if let Ok(ServerInfo {
+            context_length: Some(context_length),
+        }) = server_info
+        {
+            for model in &mut models {
+                model.max_model_len.get_or_insert(context_length);
+            }
+        }
+        Ok((models, metrics))
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ServerInfo {
+    context_length: Option<u64>,
+}
+
+#[tracing::instrument(err(level = tracing::Level::WARN), skip_all)]
+async fn get_server_info(client: &client::Client) -> Result<ServerInfo, Error> {
+    let response = super::get(client, "/get_server_info").await?;
+    Ok(serde_json::from_slice(response.body())?)
+}
+
+#[tracing::instrument(err(level = tracing::Level::WARN), skip_all)]
+async fn scrape_metrics(client: &client::Client) -> Result<schemas::Metrics, Error> {
+    let response = super::get(client, "/metrics").await?;
+    Ok(misc::metrics::parse_sglang(str::from_utf8(
+        response.body(),
+    )?)?)
+}
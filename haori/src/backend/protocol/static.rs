@@ -0,0 +1,102 @@
+use crate::{Error, client};
+use std::collections::{BTreeMap, BTreeSet};
+use std::future;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Config {
+    models: Models,
+    #[serde(default)]
+    metrics: Metrics,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+enum Models {
+    #[serde(rename = "list")]
+    List(Vec<String>),
+    #[serde(rename = "fetch")]
+    Fetch {
+        allowlist: Option<BTreeSet<String>>,
+        #[serde(with = "humantime_serde")]
+        #[schemars(with = "String")]
+        retry_delay: Duration,
+    },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Metrics {
+    #[serde(default)]
+    num_requests_running: u32,
+    #[serde(default)]
+    num_requests_waiting: u32,
+}
+
+pub(super) fn watch(
+    client: client::Client,
+    config: Config,
+) -> impl futures::Stream<Item = Result<Vec<schemas::Provider>, Error>> + Send {
+    struct State {
+        client: client::Client,
+        id: uuid::Uuid,
+        config: Config,
+        sleep: Option<tokio::time::Sleep>,
+    }
+
+    impl State {
+        async fn next(&mut self) -> Result<schemas::Provider, Error> {
+            let models = match &self.config.models {
+                Models::List(models) => models
+                    .iter()
+                    .map(|model| schemas::Model::new(model.clone()))
+                    .collect(),
+                Models::Fetch { allowlist, .. } => {
+                    let mut models = super::list_models(&self.client).await?;
+                    if let Some(allowlist) = allowlist {
+                        models.retain(|model| allowlist.contains(&model.id));
+                    }
+                    models
+                }
+            };
+            let provider = schemas::Provider {
+                id: self.id,
+                models,
+                metrics: schemas::Metrics {
+                    vllm_num_requests_running: Some(self.config.metrics.num_requests_running),
+                    vllm_num_requests_waiting: Some(self.config.metrics.num_requests_waiting),
+                    ..schemas::Metrics::default()
+                },
+                labels: BTreeMap::new(),
+                protocol: None,
+            };
+            Ok(provider)
+        }
+    }
+
+    let state = State {
+        client,
+        id: uuid::Uuid::new_v4(),
+        config,
+        sleep: None,
+    };
+    futures::stream::unfold(Some(state), async |state| {
+        let Some(mut state) = state else {
+            return future::pending().await;
+        };
+        if let Some(sleep) = state.sleep.take() {
+            sleep.await;
+        }
+        match state.next().await {
+            Ok(provider) => Some((Ok(vec![provider]), None)),
+            Err(e) if client::is_closed(&e) => None,
+            Err(e) => {
+                if let Models::Fetch { retry_delay, .. } = &state.config.models {
+                    state.sleep = Some(tokio::time::sleep(*retry_delay));
+                }
+                Some((Err(e), Some(state)))
+            }
+        }
+    })
+}
@@ -0,0 +1,60 @@
+use crate::{Error, client};
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Config {
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    interval: Duration,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    timeout: Duration,
+}
+
+pub(super) fn watch(
+    client: client::Client,
+    config: Config,
+) -> impl futures::Stream<Item = Result<Vec<schemas::Provider>, Error>> + Send {
+    super::poll(client, config.interval, config.timeout, State)
+}
+
+struct State;
+
+impl super::Probe for State {
+    async fn probe(
+        &mut self,
+        client: &client::Client,
+    ) -> Result<(Vec<schemas::Model>, schemas::Metrics), Error> {
+        let (info, metrics) =
+            futures::future::try_join(get_info(client), scrape_metrics(client)).await?;
+        Ok((vec![info.model()], metrics))
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Info {
+    model_id: String,
+    max_total_tokens: Option<u64>,
+    max_input_length: Option<u64>,
+}
+
+impl Info {
+    fn model(self) -> schemas::Model {
+        let mut model = schemas::Model::new(self.model_id);
+        model.max_model_len = self.max_total_tokens.or(self.max_input_length);
+        model
+    }
+}
+
+#[tracing::instrument(err(level = tracing::Level::WARN), skip_all)]
+async fn get_info(client: &client::Client) -> Result<Info, Error> {
+    let response = super::get(client, "/info").await?;
+    Ok(serde_json::from_slice(response.body())?)
+}
+
+#[tracing::instrument(err(level = tracing::Level::WARN), skip_all)]
+async fn scrape_metrics(client: &client::Client) -> Result<schemas::Metrics, Error> {
+    let response = super::get(client, "/metrics").await?;
+    Ok(misc::metrics::parse_tgi(str::from_utf8(response.body())?)?)
+}
@@ -0,0 +1,68 @@
+use crate::{ConfigArgs, Error, client, endpoint};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+#[derive(clap::Args)]
+pub(super) struct Args {
+    #[clap(flatten)]
+    config: ConfigArgs,
+    #[clap(long)]
+    endpoints_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Endpoint {
+    #[serde(default = "uuid::Uuid::new_v4")]
+    id: uuid::Uuid,
+    #[serde(with = "http_serde::uri")]
+    uri: http::Uri,
+    #[serde(default)]
+    http2_prior_knowledge: bool,
+    resolve: Option<SocketAddr>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    providers: Vec<schemas::Provider>,
+}
+
+pub(super) async fn check(args: Args) -> Result<(), Error> {
+    let config = args.config.load().await?;
+
+    if let Some(endpoints_path) = &args.endpoints_path {
+        let endpoints =
+            serde_json::from_slice::<Vec<Endpoint>>(&tokio::fs::read(endpoints_path).await?)?
+                .into_iter()
+                .map(|endpoint| {
+                    let client = client::Client::standard(client::standard::Config {
+                        uri: endpoint.uri,
+                        http2_prior_knowledge: endpoint.http2_prior_knowledge,
+                        resolve: endpoint.resolve,
+                        unix_socket: None,
+                        authorization: None,
+                        tls: None,
+                    })?;
+                    Ok(endpoint::Endpoint {
+                        id: endpoint.id,
+                        client,
+                        metadata: endpoint::Metadata {
+                            labels: endpoint.labels,
+                            ..endpoint::Metadata::default()
+                        },
+                        providers: endpoint.providers,
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+        let output = config
+            .frontends
+            .iter()
+            .filter_map(|config| config.generate(&endpoints).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer_pretty(&mut stdout, &output)?;
+        writeln!(stdout)?;
+    }
+    Ok(())
+}
@@ -1,6 +1,7 @@
-use crate::Error;
-use futures::FutureExt;
+use crate::{Error, config};
+use futures::{FutureExt, TryFutureExt};
 use http_body_util::BodyExt;
+use std::fs;
 use std::iter;
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -14,6 +15,7 @@ pub struct Client {
 enum Inner {
     Standard {
         client: reqwest::Client,
+        base: http::Uri,
         config: standard::Config,
     },
     Tunnel {
@@ -42,6 +44,7 @@ pub mod standard {
         pub resolve: Option<SocketAddr>,
         pub unix_socket: Option<PathBuf>,
         pub authorization: Option<config::Authorization>,
+        pub tls: Option<config::Tls>,
     }
 }
 
@@ -76,13 +79,58 @@ impl Client {
             }
 
             builder = builder.dns_resolver(Resolve(resolve));
+        } else if config.unix_socket.is_none()
+            && let Some(tls) = &config.tls
+            && tls.server_name.is_some()
+            && let Some(host) = config.uri.host()
+        {
+            struct Alias(String);
+
+            impl reqwest::dns::Resolve for Alias {
+                fn resolve(&self, _: reqwest::dns::Name) -> reqwest::dns::Resolving {
+                    tokio::net::lookup_host((self.0.clone(), 0))
+                        .map_ok(|addrs| Box::new(addrs) as _)
+                        .err_into()
+                        .boxed()
+                }
+            }
+
+            builder = builder.dns_resolver(Alias(host.to_owned()));
         }
         if let Some(unix_socket) = &config.unix_socket {
             builder = builder.unix_socket(unix_socket.clone());
         }
+        let mut base = config.uri.clone();
+        if let Some(tls) = &config.tls {
+            for path in &tls.ca_certificates {
+                builder = builder
+                    .tls_certs_merge(reqwest::Certificate::from_pem_bundle(&fs::read(path)?)?);
+            }
+            if let Some(server_name) = &tls.server_name {
+                set_host(&mut base, server_name)?;
+            }
+            if tls.insecure {
+                builder = builder.tls_danger_accept_invalid_certs(true);
+            }
+            if let Some(min_version) = tls.min_version {
+                builder = builder.tls_version_min(match min_version {
+                    config::TlsVersion::Tls1_2 => reqwest::tls::Version::TLS_1_2,
+                    config::TlsVersion::Tls1_3 => reqwest::tls::Version::TLS_1_3,
+                });
+            }
+            if let Some(identity) = &tls.identity {
+                let mut pem = fs::read(&identity.certificate)?;
+                pem.extend(fs::read(&identity.key)?);
+                builder = builder.identity(reqwest::Identity::from_pem(&pem)?);
+            }
+        }
         let client = builder.build()?;
         Ok(Self {
-            inner: Arc::new(Inner::Standard { client, config }),
+            inner: Arc::new(Inner::Standard {
+                client,
+                base,
+                config,
+            }),
         })
     }
 
@@ -131,13 +179,15 @@ impl Client {
         request.headers_mut().remove("x-api-key");
         async move {
             match &*inner {
-                Inner::Standard { client, config } => {
-                    set_base(request.uri_mut(), config.uri.clone())?;
+                Inner::Standard {
+                    client,
+                    base,
+                    config,
+                } => {
+                    set_base(request.uri_mut(), base.clone())?;
                     if let Some(authorization) = &config.authorization {
-                        request.headers_mut().insert(
-                            http::header::AUTHORIZATION,
-                            http::HeaderValue::from_str(&authorization.value().await?)?,
-                        );
+                        let (name, value) = authorization.header().await?;
+                        request.headers_mut().insert(name, value);
                     }
                     let request = reqwest::Request::try_from(request.map(|body| {
                         reqwest::Body::wrap_stream(http_body_util::BodyDataStream::new(body))
@@ -175,7 +225,8 @@ fn set_base(uri: &mut http::Uri, base: http::Uri) -> Result<(), http::Error> {
         *path_and_query = format!(
             "{}{}",
             path_and_query.path().trim_end_matches('/'),
-            uri.path(),
+            uri.path_and_query()
+                .map_or(uri.path(), http::uri::PathAndQuery::as_str),
         )
         .parse()?;
     }
@@ -183,6 +234,17 @@ fn set_base(uri: &mut http::Uri, base: http::Uri) -> Result<(), http::Error> {
     Ok(())
 }
 
+pub fn set_host(uri: &mut http::Uri, host: &str) -> Result<(), http::Error> {
+    let mut parts = uri.clone().into_parts();
+    parts.authority = Some(if let Some(port) = uri.port() {
+        format!("{host}:{port}").parse()?
+    } else {
+        host.parse()?
+    });
+    *uri = http::Uri::from_parts(parts)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -195,5 +257,22 @@ mod tests {
 
         check("/baz", "http://foo.bar/", "http://foo.bar/baz");
         check("/qux", "http://foo.bar/baz/", "http://foo.bar/baz/qux");
+        check(
+            "/qux?a=b",
+            "http://foo.bar/baz",
+            "http://foo.bar/baz/qux?a=b",
+        );
+    }
+
+    #[test]
+    fn test_set_host() {
+        fn check(uri: &str, host: &str, expected: &str) {
+            let mut uri = uri.parse().unwrap();
+            super::set_host(&mut uri, host).unwrap();
+            assert_eq!(uri, expected);
+        }
+
+        check("https://10.0.0.1/v1", "foo.bar", "https://foo.bar/v1");
+        check("wss://10.0.0.1:8443/", "foo.bar", "wss://foo.bar:8443/");
     }
 }
@@ -1,53 +1,272 @@
-use crate::Error;
+mod token;
+
+pub use token::stale_paths;
+
+use crate::{Error, tls};
+use base64::Engine;
 use std::io;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{env, fs};
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub enum Bind {
     #[serde(rename = "tcp")]
     Tcp(SocketAddr),
     #[serde(rename = "unix")]
     Unix(PathBuf),
+    #[serde(rename = "tls")]
+    Tls(Box<TlsBind>),
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TlsBind {
+    pub bind: Bind,
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+    #[serde(default)]
+    pub client_ca_certificates: Vec<PathBuf>,
 }
 
 impl Bind {
-    pub fn bind(self) -> impl Future<Output = io::Result<tokio_net_incoming::Listener>> {
-        let bind = match self {
-            Bind::Tcp(bind) => tokio_net_incoming::OneOf::Tcp(bind),
-            Bind::Unix(bind) => tokio_net_incoming::OneOf::Unix(bind),
+    pub fn check(&self) -> Result<(), Error> {
+        match self {
+            Bind::Tcp(_) | Bind::Unix(_) => Ok(()),
+            Bind::Tls(tls) => match tls.bind {
+                Bind::Tcp(_) | Bind::Unix(_) => Ok(()),
+                Bind::Tls(_) => Err("`tls` cannot be nested")?,
+            },
+        }
+    }
+
+    pub async fn bind(
+        self,
+    ) -> Result<
+        (
+            tokio_net_incoming::Listener,
+            Option<tokio_rustls::TlsAcceptor>,
+        ),
+        Error,
+    > {
+        self.check()?;
+        let (bind, acceptor) = match self {
+            Bind::Tcp(bind) => (tokio_net_incoming::OneOf::Tcp(bind), None),
+            Bind::Unix(bind) => (tokio_net_incoming::OneOf::Unix(bind), None),
+            Bind::Tls(tls) => {
+                let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(tls::server_config(&tls)?));
+                let bind = match tls.bind {
+                    Bind::Tcp(bind) => tokio_net_incoming::OneOf::Tcp(bind),
+                    Bind::Unix(bind) => tokio_net_incoming::OneOf::Unix(bind),
+                    Bind::Tls(_) => unreachable!(),
+                };
+                (bind, Some(acceptor))
+            }
         };
-        tokio_net_incoming::Listener::bind(bind)
+        let listener = tokio_net_incoming::Listener::bind(bind).await?;
+        Ok((listener, acceptor))
     }
 }
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub enum Authorization {
     #[serde(rename = "bearer")]
     Bearer(Bearer),
+    #[serde(rename = "basic")]
+    Basic(Basic),
+    #[serde(rename = "header")]
+    Header(Header),
 }
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Bearer {
-    token: Token,
+    token: token::Token,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Basic {
+    username: String,
+    password: token::Token,
 }
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
-pub enum Token {
-    #[serde(rename = "path")]
-    Path(PathBuf),
+pub struct Header {
+    #[serde(deserialize_with = "deserialize_header_name")]
+    #[schemars(with = "String")]
+    name: http::HeaderName,
+    value: token::Token,
 }
 
 impl Authorization {
-    pub async fn value(&self) -> Result<String, Error> {
+    pub fn check(&self) -> Result<(), Error> {
         match self {
-            Self::Bearer(Bearer {
-                token: Token::Path(path),
-            }) => Ok(format!("Bearer {}", tokio::fs::read_to_string(path).await?)),
+            Self::Bearer(Bearer { token }) => token.check(),
+            Self::Basic(Basic { password, .. }) => password.check(),
+            Self::Header(Header { value, .. }) => value.check(),
         }
     }
+
+    pub async fn header(&self) -> Result<(http::HeaderName, http::HeaderValue), Error> {
+        let (name, value) = match self {
+            Self::Bearer(Bearer { token }) => (
+                http::header::AUTHORIZATION,
+                format!("Bearer {}", token.value().await?),
+            ),
+            Self::Basic(Basic { username, password }) => {
+                let credentials = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{username}:{}", password.value().await?));
+                (http::header::AUTHORIZATION, format!("Basic {credentials}"))
+            }
+            Self::Header(Header { name, value }) => (name.clone(), value.value().await?),
+        };
+        let mut value = http::HeaderValue::try_from(value)?;
+        value.set_sensitive(true);
+        Ok((name, value))
+    }
+}
+
+fn deserialize_header_name<'de, D>(deserializer: D) -> Result<http::HeaderName, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name = <String as serde::Deserialize>::deserialize(deserializer)?;
+    http::HeaderName::try_from(name).map_err(serde::de::Error::custom)
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    #[serde(default)]
+    pub ca_certificates: Vec<PathBuf>,
+    pub server_name: Option<String>,
+    #[serde(default)]
+    pub insecure: bool,
+    pub min_version: Option<TlsVersion>,
+    pub identity: Option<Identity>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls1_2,
+    #[serde(rename = "1.3")]
+    Tls1_3,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Identity {
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+}
+
+pub fn parse(path: &Path, data: &[u8]) -> Result<serde_json::Value, Error> {
+    let value = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => toml::from_slice(data)?,
+        Some("yaml" | "yml") => serde_norway::from_slice(data)?,
+        _ => serde_json::from_slice(data)?,
+    };
+    Ok(value)
+}
+
+pub fn interpolate(value: &mut serde_json::Value) -> Result<(), Error> {
+    match value {
+        serde_json::Value::String(value) => {
+            *value = interpolate_str(value, |name| env::var(name).ok())?;
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                interpolate(value)?;
+            }
+        }
+        serde_json::Value::Object(values) => {
+            for value in values.values_mut() {
+                interpolate(value)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn interpolate_str<F>(value: &str, var: F) -> Result<String, Error>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    #[derive(Debug, thiserror::Error)]
+    #[error("environment variable `{0}` is not set")]
+    struct NotPresentError(String);
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("failed to read `{0}`: {1}")]
+    struct FileError(String, io::Error);
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("unterminated `${{` in {0:?}")]
+    struct UnterminatedError(String);
+
+    let mut output = String::new();
+    let mut rest = value;
+    while let Some(index) = rest.find('$') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index..];
+        if let Some(rest_next) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = rest_next;
+        } else if let Some(rest_next) = rest.strip_prefix("${") {
+            let (expr, rest_next) = rest_next
+                .split_once('}')
+                .ok_or_else(|| UnterminatedError(value.to_owned()))?;
+            let (name, default) = match expr.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (expr, None),
+            };
+            let value = if let Some(path) = name.strip_prefix("file:") {
+                let value = fs::read_to_string(path).map_err(|e| FileError(path.to_owned(), e))?;
+                Some(value.trim_end_matches('\n').to_owned())
+            } else {
+                var(name)
+            };
+            match (value, default) {
+                (Some(value), Some(default)) if value.is_empty() => output.push_str(default),
+                (Some(value), _) => output.push_str(&value),
+                (None, Some(default)) => output.push_str(default),
+                (None, None) => Err(NotPresentError(name.to_owned()))?,
+            }
+            rest = rest_next;
+        } else {
+            output.push('$');
+            rest = &rest[1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_interpolate_str() {
+        fn check(value: &str, expected: Option<&str>) {
+            let var = |name: &str| match name {
+                "FOO" => Some("foo".to_owned()),
+                "EMPTY" => Some(String::new()),
+                _ => None,
+            };
+            assert_eq!(super::interpolate_str(value, var).ok().as_deref(), expected);
+        }
+
+        check("http://${FOO}:8080/", Some("http://foo:8080/"));
+        check("${BAR:-bar}/${EMPTY:-baz}", Some("bar/baz"));
+        check("${EMPTY}", Some(""));
+        check("$${FOO} $FOO", Some("${FOO} $FOO"));
+        check("${BAR}", None);
+        check("${FOO", None);
+    }
 }
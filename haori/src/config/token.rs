@@ -0,0 +1,266 @@
+use crate::Error;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+use std::{env, fs};
+use tokio::time::Instant;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const STALE_WINDOW: Duration = Duration::from_secs(60);
+const OAUTH2_TIMEOUT: Duration = Duration::from_secs(30);
+const OAUTH2_DEFAULT_EXPIRES_IN: Duration = Duration::from_secs(300);
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(reqwest::Client::new);
+static STALE: LazyLock<Mutex<HashMap<PathBuf, Instant>>> = LazyLock::new(Mutex::default);
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub enum Token {
+    #[serde(rename = "path")]
+    Path(#[schemars(with = "PathBuf")] Path),
+    #[serde(rename = "inline")]
+    Inline(String),
+    #[serde(rename = "env")]
+    Env(String),
+    #[serde(rename = "exec")]
+    Exec(Exec),
+    #[serde(rename = "oauth2")]
+    OAuth2(OAuth2),
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(from = "PathBuf")]
+pub struct Path {
+    path: PathBuf,
+    cache: Cache<PathState>,
+}
+
+#[derive(Debug)]
+struct PathState {
+    value: String,
+    modified: SystemTime,
+    checked: Instant,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Exec {
+    command: Vec<String>,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    ttl: Duration,
+    #[serde(skip)]
+    cache: Cache<(String, Option<Instant>)>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OAuth2 {
+    #[serde(with = "http_serde::uri")]
+    #[schemars(with = "String")]
+    token_uri: http::Uri,
+    client_id: String,
+    client_secret: Box<Token>,
+    scope: Option<String>,
+    #[serde(default, with = "humantime_serde")]
+    #[schemars(with = "Option<String>")]
+    refresh_before: Duration,
+    #[serde(skip)]
+    cache: Cache<(String, Option<Instant>)>,
+}
+
+impl Token {
+    pub fn check(&self) -> Result<(), Error> {
+        match self {
+            Self::Path(path) => {
+                fs::File::open(&path.path)
+                    .map_err(|e| format!("{e}: `{}`", path.path.display()))?;
+                Ok(())
+            }
+            Self::OAuth2(oauth2) => oauth2.client_secret.check(),
+            Self::Inline(_) | Self::Env(_) | Self::Exec(_) => Ok(()),
+        }
+    }
+
+    pub async fn value(&self) -> Result<String, Error> {
+        match self {
+            Self::Path(path) => path.value().await,
+            Self::Inline(value) => Ok(value.clone()),
+            Self::Env(name) => Ok(env::var(name).map_err(|e| format!("{e}: `{name}`"))?),
+            Self::Exec(exec) => exec.value().await,
+            Self::OAuth2(oauth2) => oauth2.value().await,
+        }
+    }
+}
+
+impl From<PathBuf> for Path {
+    fn from(path: PathBuf) -> Self {
+        Self {
+            path,
+            cache: Cache::default(),
+        }
+    }
+}
+
+impl Path {
+    async fn value(&self) -> Result<String, Error> {
+        let mut state = self.cache.0.lock().await;
+        if let Some(state) = &*state
+            && state.checked.elapsed() < CHECK_INTERVAL
+        {
+            return Ok(state.value.clone());
+        }
+
+        let result = async {
+            let modified = tokio::fs::metadata(&self.path).await?.modified()?;
+            if let Some(state) = &*state
+                && state.modified == modified
+            {
+                Ok(None)
+            } else {
+                let value = tokio::fs::read_to_string(&self.path).await?;
+                Ok::<_, Error>(Some((value.trim().to_owned(), modified)))
+            }
+        }
+        .await;
+        let mut stale = STALE.lock().unwrap();
+        match (result, &mut *state) {
+            (Ok(Some((value, modified))), state) => {
+                stale.remove(&self.path);
+                *state = Some(PathState {
+                    value: value.clone(),
+                    modified,
+                    checked: Instant::now(),
+                });
+                Ok(value)
+            }
+            (Ok(None), Some(state)) => {
+                stale.remove(&self.path);
+                state.checked = Instant::now();
+                Ok(state.value.clone())
+            }
+            (Err(e), Some(state)) => {
+                tracing::warn!(path = ?self.path, error = e.to_string());
+                stale.insert(self.path.clone(), Instant::now());
+                state.checked = Instant::now();
+                Ok(state.value.clone())
+            }
+            (Err(e), None) => {
+                stale.insert(self.path.clone(), Instant::now());
+                Err(e)
+            }
+            (Ok(None), None) => unreachable!(),
+        }
+    }
+}
+
+pub fn stale_paths() -> Vec<PathBuf> {
+    STALE
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, failed)| failed.elapsed() < STALE_WINDOW)
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+impl Exec {
+    async fn value(&self) -> Result<String, Error> {
+        self.cache
+            .get_or_try_insert(|| async {
+                let (program, args) = self.command.split_first().ok_or("empty command")?;
+                let output = tokio::process::Command::new(program)
+                    .args(args)
+                    .kill_on_drop(true)
+                    .output()
+                    .await?;
+                if !output.status.success() {
+                    Err(format!("`{program}` exited with {}", output.status))?
+                }
+                let value = String::from_utf8(output.stdout)?.trim().to_owned();
+                Ok((value, Instant::now().checked_add(self.ttl)))
+            })
+            .await
+    }
+}
+
+impl OAuth2 {
+    async fn value(&self) -> Result<String, Error> {
+        #[derive(serde::Deserialize)]
+        struct Response {
+            access_token: String,
+            expires_in: Option<u64>,
+        }
+
+        self.cache
+            .get_or_try_insert(|| async {
+                let client_secret = Box::pin(self.client_secret.value()).await?;
+                let mut form = vec![
+                    ("grant_type", "client_credentials"),
+                    ("client_id", self.client_id.as_str()),
+                    ("client_secret", client_secret.as_str()),
+                ];
+                if let Some(scope) = &self.scope {
+                    form.push(("scope", scope.as_str()));
+                }
+                let now = Instant::now();
+                let Response {
+                    access_token,
+                    expires_in,
+                } = CLIENT
+                    .post(self.token_uri.to_string())
+                    .form(&form)
+                    .timeout(OAUTH2_TIMEOUT)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                let expires_in = expires_in.map_or(OAUTH2_DEFAULT_EXPIRES_IN, Duration::from_secs);
+                let deadline = now.checked_add(expires_in.saturating_sub(self.refresh_before));
+                Ok((access_token, deadline))
+            })
+            .await
+    }
+}
+
+#[derive(Debug)]
+struct Cache<T>(Arc<tokio::sync::Mutex<Option<T>>>);
+
+impl<T> Clone for Cache<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Default for Cache<T> {
+    fn default() -> Self {
+        Self(Arc::default())
+    }
+}
+
+impl<T> PartialEq for Cache<T> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Cache<(String, Option<Instant>)> {
+    async fn get_or_try_insert<F, Fut>(&self, f: F) -> Result<String, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(String, Option<Instant>), Error>>,
+    {
+        let mut cache = self.0.lock().await;
+        if let Some((value, deadline)) = &*cache
+            && deadline.is_none_or(|deadline| Instant::now() < deadline)
+        {
+            Ok(value.clone())
+        } else {
+            let (value, deadline) = f().await?;
+            *cache = Some((value.clone(), deadline));
+            Ok(value)
+        }
+    }
+}
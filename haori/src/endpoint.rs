@@ -1,8 +1,27 @@
 use crate::client;
+use std::collections::BTreeMap;
 
 #[derive(Debug)]
 pub struct Endpoint {
     pub id: uuid::Uuid,
     pub client: client::Client,
+    pub metadata: Metadata,
     pub providers: Vec<schemas::Provider>,
 }
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Metadata {
+    pub labels: BTreeMap<String, String>,
+    pub priority: Option<u16>,
+    pub weight: u16,
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Self {
+            labels: BTreeMap::new(),
+            priority: None,
+            weight: 1,
+        }
+    }
+}
@@ -2,24 +2,79 @@ mod connection;
 mod protocol;
 
 use crate::{Error, endpoint};
-use futures::TryFutureExt;
+use futures::FutureExt;
+use futures::future::Either;
+use std::future;
+use std::pin;
 use std::sync::Arc;
+use std::task::Poll;
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub(super) struct Config {
     connection: connection::Config,
     protocol: protocol::Config,
 }
 
+impl Config {
+    pub(super) fn check(&self) -> Result<(), Error> {
+        connection::check(&self.connection)?;
+        protocol::check(&self.connection, &self.protocol)
+    }
+
+    pub(super) fn generate(
+        &self,
+        endpoints: &[endpoint::Endpoint],
+    ) -> Result<Option<serde_json::Value>, Error> {
+        protocol::generate(&self.protocol, endpoints)
+    }
+}
+
 type Receiver = tokio::sync::watch::Receiver<Option<(usize, Arc<[endpoint::Endpoint]>)>>;
 
-pub(super) async fn serve(config: Vec<Config>, rx: Receiver) -> Result<(), Error> {
-    futures::future::try_join_all(
-        config
-            .into_iter()
-            .map(|config| protocol::serve(config.connection, config.protocol, rx.clone())),
-    )
-    .map_ok(|_| ())
-    .await
+pub(super) async fn serve(
+    mut config_rx: tokio::sync::watch::Receiver<Vec<Config>>,
+    rx: Receiver,
+) -> Result<(), Error> {
+    let mut serves = Vec::<(Config, futures::future::LocalBoxFuture<'static, _>)>::new();
+    loop {
+        let mut serves_next = Vec::new();
+        for config in config_rx.borrow_and_update().iter() {
+            if let Some(index) = serves.iter().position(|(running, _)| running == config) {
+                serves_next.push(serves.swap_remove(index));
+            } else {
+                let serve = protocol::serve(
+                    config.connection.clone(),
+                    config.protocol.clone(),
+                    rx.clone(),
+                );
+                serves_next.push((config.clone(), serve.boxed_local()));
+            }
+        }
+        serves = serves_next;
+
+        let output = {
+            let changed = async {
+                if config_rx.changed().await.is_err() {
+                    future::pending().await
+                }
+            };
+            let serve = future::poll_fn(|cx| {
+                for (index, (_, serve)) in serves.iter_mut().enumerate() {
+                    if let Poll::Ready(output) = serve.as_mut().poll(cx) {
+                        return Poll::Ready((index, output));
+                    }
+                }
+                Poll::Pending
+            });
+            match futures::future::select(pin::pin!(changed), serve).await {
+                Either::Left(_) => None,
+                Either::Right((output, _)) => Some(output),
+            }
+        };
+        if let Some((index, output)) = output {
+            output?;
+            serves[index].1 = future::pending().boxed_local();
+        }
+    }
 }
@@ -1,7 +1,7 @@
-use crate::config;
+use crate::{Error, config};
 use std::time::Duration;
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub(super) enum Config {
     #[serde(rename = "standard")]
@@ -9,11 +9,27 @@ pub(super) enum Config {
     #[serde(rename = "tunnel")]
     Tunnel {
         #[serde(with = "http_serde::uri")]
+        #[schemars(with = "String")]
         uri: http::Uri,
         authorization: Option<config::Authorization>,
+        tls: Option<config::Tls>,
         #[serde(with = "humantime_serde")]
+        #[schemars(with = "String")]
         keep_alive_interval: Duration,
         #[serde(with = "humantime_serde")]
+        #[schemars(with = "String")]
         retry_delay: Duration,
     },
 }
+
+pub(super) fn check(config: &Config) -> Result<(), Error> {
+    match config {
+        Config::Standard { bind } => bind.check(),
+        Config::Tunnel { authorization, .. } => {
+            if let Some(authorization) = authorization {
+                authorization.check()?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -2,13 +2,14 @@ mod envoy_xds;
 mod native;
 
 use super::{Receiver, connection};
-use crate::Error;
+use crate::{Error, endpoint};
+use std::collections::BTreeMap;
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub(super) struct Config(Inner);
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 enum Inner {
     #[serde(rename = "native")]
@@ -17,6 +18,81 @@ enum Inner {
     EnvoyXds(envoy_xds::Config),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct Load {
+    num_requests_waiting: f64,
+    num_requests_running: f64,
+    kv_cache_usage_perc: f64,
+    num_preemptions_per_second: f64,
+    generation_tokens_per_second: f64,
+    time_to_first_token_seconds_p99: f64,
+    time_per_output_token_seconds_p99: f64,
+}
+
+impl Default for Load {
+    fn default() -> Self {
+        Self {
+            num_requests_waiting: 1.,
+            num_requests_running: 0.,
+            kv_cache_usage_perc: 0.,
+            num_preemptions_per_second: 0.,
+            generation_tokens_per_second: 0.,
+            time_to_first_token_seconds_p99: 0.,
+            time_per_output_token_seconds_p99: 0.,
+        }
+    }
+}
+
+impl Load {
+    fn check(&self) -> Result<(), Error> {
+        if self
+            .terms(&schemas::Metrics::default())
+            .any(|(coefficient, _)| !(coefficient >= 0. && coefficient.is_finite()))
+        {
+            Err("`load` coefficients must be finite and non-negative")?
+        }
+        Ok(())
+    }
+
+    fn terms(&self, metrics: &schemas::Metrics) -> impl Iterator<Item = (f64, Option<f64>)> {
+        [
+            (
+                self.num_requests_waiting,
+                metrics.vllm_num_requests_waiting.map(f64::from),
+            ),
+            (
+                self.num_requests_running,
+                metrics.vllm_num_requests_running.map(f64::from),
+            ),
+            (self.kv_cache_usage_perc, metrics.vllm_kv_cache_usage_perc),
+            (
+                self.num_preemptions_per_second,
+                metrics.vllm_num_preemptions_per_second,
+            ),
+            (
+                self.generation_tokens_per_second,
+                metrics.vllm_generation_tokens_per_second,
+            ),
+            (
+                self.time_to_first_token_seconds_p99,
+                metrics.vllm_time_to_first_token_seconds_p99,
+            ),
+            (
+                self.time_per_output_token_seconds_p99,
+                metrics.vllm_time_per_output_token_seconds_p99,
+            ),
+        ]
+        .into_iter()
+    }
+
+    fn value(&self, metrics: &schemas::Metrics) -> f64 {
+        self.terms(metrics)
+            .map(|(coefficient, value)| coefficient * value.unwrap_or_default())
+            .sum()
+    }
+}
+
 pub(super) async fn serve(
     connection: connection::Config,
     config: Config,
@@ -27,3 +103,55 @@ pub(super) async fn serve(
         Inner::EnvoyXds(config) => envoy_xds::serve(connection, config, rx).await,
     }
 }
+
+pub(super) fn check(connection: &connection::Config, config: &Config) -> Result<(), Error> {
+    match &config.0 {
+        Inner::Native(config) => native::check(config),
+        Inner::EnvoyXds(config) => envoy_xds::check(connection, config),
+    }
+}
+
+pub(super) fn generate(
+    config: &Config,
+    endpoints: &[endpoint::Endpoint],
+) -> Result<Option<serde_json::Value>, Error> {
+    match &config.0 {
+        Inner::Native(_) => Ok(None),
+        Inner::EnvoyXds(config) => {
+            let (clusters, route_configuration) = envoy_xds::generate(config, endpoints)?;
+            Ok(Some(serde_json::json!({
+                "clusters": clusters,
+                "route_configuration": route_configuration,
+            })))
+        }
+    }
+}
+
+fn list_models<'a, I>(providers: I, model_status: bool) -> Vec<schemas::Model>
+where
+    I: IntoIterator<Item = &'a schemas::Provider>,
+{
+    let mut models = BTreeMap::<_, schemas::Model>::new();
+    for provider in providers {
+        for model in &provider.models {
+            let merged = models
+                .entry(model.id.as_str())
+                .and_modify(|merged| merged.merge(model))
+                .or_insert_with(|| {
+                    let mut model = model.clone();
+                    model.haori = None;
+                    model
+                });
+            if model_status {
+                let metrics = provider.metrics.model(&model.id);
+                let status = merged.haori.get_or_insert_default();
+                status.replicas += 1;
+                status.num_requests_running +=
+                    metrics.vllm_num_requests_running.unwrap_or_default();
+                status.num_requests_waiting +=
+                    metrics.vllm_num_requests_waiting.unwrap_or_default();
+            }
+        }
+    }
+    models.into_values().collect()
+}
@@ -1,11 +1,12 @@
 use super::{Receiver, connection};
-use crate::{Error, client, endpoint, header};
+use crate::{Error, client, config, endpoint, header, tls};
 use futures::future::Either;
 use futures::{StreamExt, TryFutureExt, TryStreamExt};
 use prost::Name;
 use std::collections::BTreeMap;
 use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Duration;
 use tonic_envoy::envoy::config::cluster::v3 as cluster_v3;
 use tonic_envoy::envoy::config::core::v3 as core_v3;
 use tonic_envoy::envoy::config::endpoint::v3 as endpoint_v3;
@@ -14,15 +15,23 @@ use tonic_envoy::envoy::service::discovery::v3 as discovery_v3;
 use tonic_envoy::envoy::service::discovery::v3::aggregated_discovery_service_server;
 use tonic_envoy::envoy::r#type::matcher::v3 as matcher_v3;
 
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub(super) type Config = Arc<Inner>;
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub(super) struct Inner {
     route_config_name: String,
     metadata_namespace: String,
+    #[schemars(with = "Option<serde_json::Map<String, serde_json::Value>>")]
     template_cluster: Option<cluster_v3::Cluster>,
+    #[schemars(with = "Option<serde_json::Map<String, serde_json::Value>>")]
     template_route: Option<route_v3::Route>,
+    #[serde(default)]
+    model_status: bool,
+    #[serde(default)]
+    load: super::Load,
 }
 
 pub(super) async fn serve(
@@ -30,6 +39,8 @@ pub(super) async fn serve(
     config: Config,
     mut rx: Receiver,
 ) -> Result<(), Error> {
+    check(&connection, &config)?;
+
     let reflection_service = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
         .register_encoded_file_descriptor_set(tonic_envoy::FILE_DESCRIPTOR_SET)
@@ -51,10 +62,15 @@ pub(super) async fn serve(
         connection::Config::Standard { bind } => {
             futures::future::try_join(
                 async {
-                    let listener = bind.bind().await?;
-                    server
-                        .serve_with_incoming(tokio_net_incoming::ListenerStream::new(listener))
-                        .await?;
+                    let (listener, acceptor) = bind.bind().await?;
+                    let incoming = tokio_net_incoming::ListenerStream::new(listener);
+                    if let Some(acceptor) = acceptor {
+                        server
+                            .serve_with_incoming(tls::incoming(incoming, acceptor))
+                            .await?;
+                    } else {
+                        server.serve_with_incoming(incoming).await?;
+                    }
                     Ok(())
                 },
                 async {
@@ -62,21 +78,39 @@ pub(super) async fn serve(
                         aggregated_discovery_service_server::AggregatedDiscoveryServiceServer<
                             Server,
                         >;
+                    let mut serving = None;
                     loop {
-                        if rx.borrow_and_update().is_some() {
-                            health_reporter.set_serving::<Service>().await;
-                        } else {
-                            health_reporter.set_not_serving::<Service>().await;
+                        let next =
+                            rx.borrow_and_update().is_some() && config::stale_paths().is_empty();
+                        if serving != Some(next) {
+                            if next {
+                                health_reporter.set_serving::<Service>().await;
+                            } else {
+                                health_reporter.set_not_serving::<Service>().await;
+                            }
+                            serving = Some(next);
+                        }
+                        if let Ok(changed) =
+                            tokio::time::timeout(HEALTH_CHECK_INTERVAL, rx.changed()).await
+                        {
+                            changed?;
                         }
-                        rx.changed().await?;
                     }
                 },
             )
             .map_ok(|_: (_, Infallible)| ())
             .await
         }
+        connection::Config::Tunnel { .. } => unreachable!(),
+    }
+}
+
+pub(super) fn check(connection: &connection::Config, config: &Inner) -> Result<(), Error> {
+    match connection {
+        connection::Config::Standard { .. } => (),
         connection::Config::Tunnel { .. } => Err("`tunnel` and `envoy-xds` do not work together")?,
     }
+    config.load.check()
 }
 
 struct Server {
@@ -163,7 +197,7 @@ impl aggregated_discovery_service_server::AggregatedDiscoveryService for Server
     }
 }
 
-fn generate(
+pub(super) fn generate(
     config: &Config,
     endpoints: &[endpoint::Endpoint],
 ) -> Result<(Vec<cluster_v3::Cluster>, route_v3::RouteConfiguration), Error> {
@@ -180,6 +214,7 @@ fn generate(
                 resolve: Some(resolve),
                 unix_socket: None,
                 authorization: None,
+                tls: None,
             }) = endpoint.client.config()
                 && uri.scheme() == Some(&http::uri::Scheme::HTTP)
             {
@@ -215,6 +250,9 @@ fn generate(
                     ..endpoint_v3::Endpoint::default()
                 },
             )),
+            load_balancing_weight: Some(pbjson_types::UInt32Value::from(u32::from(
+                endpoint.metadata.weight.max(1),
+            ))),
             ..endpoint_v3::LbEndpoint::default()
         };
 
@@ -244,38 +282,45 @@ fn generate(
     };
 
     {
-        let mut data = endpoints
-            .iter()
-            .flat_map(|(endpoint, _)| &endpoint.providers)
-            .flat_map(|provider| &provider.models)
-            .collect::<Vec<_>>();
-        data.sort_unstable_by_key(|model| &model.id);
-
-        let mut route = config.template_route.clone().unwrap_or_default();
-        let match_ = route.r#match.get_or_insert_default();
-        match_.path_specifier = Some(route_v3::route_match::PathSpecifier::Path(
-            "/v1/models".to_owned(),
-        ));
-        match_.headers.push(route_v3::HeaderMatcher {
-            name: ":method".to_owned(),
-            header_match_specifier: Some(
-                route_v3::header_matcher::HeaderMatchSpecifier::StringMatch(
-                    matcher_v3::StringMatcher {
-                        match_pattern: Some(matcher_v3::string_matcher::MatchPattern::Exact(
-                            "GET".to_owned(),
-                        )),
-                        ..matcher_v3::StringMatcher::default()
-                    },
+        let get_route = |path: String| {
+            let mut route = config.template_route.clone().unwrap_or_default();
+            let match_ = route.r#match.get_or_insert_default();
+            match_.path_specifier = Some(route_v3::route_match::PathSpecifier::Path(path));
+            match_.headers.push(route_v3::HeaderMatcher {
+                name: ":method".to_owned(),
+                header_match_specifier: Some(
+                    route_v3::header_matcher::HeaderMatchSpecifier::StringMatch(
+                        matcher_v3::StringMatcher {
+                            match_pattern: Some(matcher_v3::string_matcher::MatchPattern::Exact(
+                                "GET".to_owned(),
+                            )),
+                            ..matcher_v3::StringMatcher::default()
+                        },
+                    ),
                 ),
-            ),
-            ..route_v3::HeaderMatcher::default()
-        });
+                ..route_v3::HeaderMatcher::default()
+            });
+            route
+        };
+
+        let data = super::list_models(
+            endpoints
+                .iter()
+                .flat_map(|(endpoint, _)| &endpoint.providers),
+            config.model_status,
+        );
+        for model in &data {
+            let mut route = get_route(format!("/v1/models/{}", model.id));
+            misc::envoy::direct_response_json(&mut route, model)?;
+            virtual_host.routes.push(route);
+        }
+        let mut route = get_route("/v1/models".to_owned());
         misc::envoy::direct_response_json(&mut route, &schemas::List { data })?;
         virtual_host.routes.push(route);
     }
 
     {
-        let mut models = BTreeMap::<_, BTreeMap<_, Vec<_>>>::new();
+        let mut models = BTreeMap::<_, BTreeMap<_, (_, Vec<_>)>>::new();
         for (endpoint, _) in &endpoints {
             for provider in &endpoint.providers {
                 for model in &provider.models {
@@ -283,23 +328,22 @@ fn generate(
                         .entry(&model.id)
                         .or_default()
                         .entry(endpoint.id)
-                        .or_default()
-                        .push(
-                            provider
-                                .metrics
-                                .vllm_num_requests_waiting
-                                .unwrap_or_default(),
-                        );
+                        .or_insert_with(|| (&endpoint.metadata, Vec::new()))
+                        .1
+                        .push(config.load.value(provider.metrics.model(&model.id)));
                 }
             }
         }
 
         for (model_id, endpoints) in models {
-            let waiting_max = endpoints
+            let load_max = endpoints
                 .values()
-                .flat_map(|endpoints| endpoints.iter().copied())
-                .max()
-                .unwrap_or_default();
+                .flat_map(|(_, load)| load.iter().copied())
+                .fold(0., f64::max);
+            let priority = endpoints
+                .values()
+                .map(|(metadata, _)| metadata.priority)
+                .min_by_key(|priority| (priority.is_none(), *priority));
 
             let mut route = config.template_route.clone().unwrap_or_default();
             let match_ = route.r#match.get_or_insert_default();
@@ -340,20 +384,22 @@ fn generate(
                 &mut action.cluster_specifier,
                 route_v3::route_action::ClusterSpecifier::WeightedClusters
             );
-            cluster_specifier.clusters.extend(endpoints.into_iter().map(
-                |(endpoint_id, waiting)| {
-                    route_v3::weighted_cluster::ClusterWeight {
-                        name: cluster_name(endpoint_id),
-                        weight: Some(pbjson_types::UInt32Value::from(
-                            waiting
-                                .into_iter()
-                                .map(|waiting| (1 + waiting_max) / (1 + waiting))
-                                .sum::<u32>(),
-                        )),
-                        ..route_v3::weighted_cluster::ClusterWeight::default()
-                    }
-                },
-            ));
+            cluster_specifier.clusters.extend(
+                endpoints
+                    .into_iter()
+                    .filter(|(_, (metadata, _))| Some(metadata.priority) == priority)
+                    .map(|(endpoint_id, (metadata, load))| {
+                        route_v3::weighted_cluster::ClusterWeight {
+                            name: cluster_name(endpoint_id),
+                            weight: Some(pbjson_types::UInt32Value::from(cluster_weight(
+                                metadata.weight,
+                                &load,
+                                load_max,
+                            ))),
+                            ..route_v3::weighted_cluster::ClusterWeight::default()
+                        }
+                    }),
+            );
             virtual_host.routes.push(route);
         }
     }
@@ -367,3 +413,26 @@ fn generate(
 
     Ok((clusters, route_configuration))
 }
+
+fn cluster_weight(weight: u16, load: &[f64], load_max: f64) -> u32 {
+    let weight = f64::from(weight.max(1))
+        * load
+            .iter()
+            .map(|load| (1. + load_max) / (1. + load))
+            .sum::<f64>();
+    ((weight * 1000.).round() as u32).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_cluster_weight() {
+        assert_eq!(super::cluster_weight(1, &[0.], 0.), 1000);
+        assert_eq!(super::cluster_weight(0, &[0.], 0.), 1000);
+        assert_eq!(super::cluster_weight(1, &[0.5], 1.), 1333);
+        assert_eq!(super::cluster_weight(1, &[1.], 1.), 1000);
+        assert_eq!(super::cluster_weight(3, &[0., 1.], 1.), 9000);
+        assert_eq!(super::cluster_weight(1, &[], 0.), 1);
+        assert_eq!(super::cluster_weight(u16::MAX, &[0.], f64::MAX), u32::MAX);
+    }
+}
@@ -1,24 +1,60 @@
 use super::{Receiver, connection};
-use crate::{Error, client, endpoint, header};
+use crate::{Error, client, config, endpoint, header, tls};
 use axum::response::IntoResponse;
 use axum::{extract, routing};
 use futures::{StreamExt, TryFutureExt};
 use http_body_util::BodyExt;
 use rand::distr::Distribution;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields, tag = "version")]
 pub(super) enum Config {
     #[serde(rename = "1")]
     V1 {
         body_limit: Option<usize>,
         #[serde(with = "humantime_serde")]
+        #[schemars(with = "String")]
         keep_alive_interval: Duration,
+        #[serde(default)]
+        label_routes: Vec<LabelRoute>,
+        #[serde(default)]
+        lora_adapters: Vec<LoraAdapter>,
+        #[serde(default)]
+        discover_lora_adapters: bool,
+        #[serde(default)]
+        model_status: bool,
+        #[serde(default)]
+        load: super::Load,
     },
 }
 
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct LabelRoute {
+    header: String,
+    label: String,
+    mode: LabelRouteMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+enum LabelRouteMode {
+    #[serde(rename = "select")]
+    Select,
+    #[serde(rename = "prefer")]
+    Prefer,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct LoraAdapter {
+    name: String,
+    path: String,
+    base_model: String,
+}
+
 pub(super) async fn serve(
     connection: connection::Config,
     config: Config,
@@ -27,10 +63,16 @@ pub(super) async fn serve(
     let Config::V1 {
         body_limit,
         keep_alive_interval,
+        label_routes,
+        lora_adapters,
+        discover_lora_adapters,
+        model_status,
+        load,
     } = config;
     let app = axum::Router::new()
         .route("/health", routing::get(health))
         .route("/v1/models", routing::get(list_models))
+        .route("/v1/models/{*model}", routing::get(get_model))
         .route("/providers", routing::get(stream_providers))
         .fallback(routing::any(fallback))
         .layer(tower::util::option_layer(
@@ -39,31 +81,34 @@ pub(super) async fn serve(
         .layer(tower_http::trace::TraceLayer::new_for_http())
         .with_state(State {
             keep_alive_interval,
+            label_routes: label_routes.into(),
+            lora_adapters: lora_adapters.into(),
+            lora_loads: Arc::default(),
+            discover_lora_adapters,
+            model_status,
+            load,
             rx,
         });
 
     match connection {
         connection::Config::Standard { bind } => {
-            let listener = bind.bind().await?;
-            axum::serve(listener, app).await?;
+            let (listener, acceptor) = bind.bind().await?;
+            if let Some(acceptor) = acceptor {
+                axum::serve(tls::Listener::new(listener, acceptor), app).await?;
+            } else {
+                axum::serve(listener, app).await?;
+            }
         }
         connection::Config::Tunnel {
             uri,
             authorization,
+            tls,
             keep_alive_interval,
             retry_delay,
         } => {
             let service = hyper_util::service::TowerToHyperService::new(app);
             let serve = async || -> Result<(), Error> {
-                let mut builder =
-                    tokio_tungstenite::tungstenite::ClientRequestBuilder::new(uri.clone());
-                if let Some(authorization) = &authorization {
-                    builder = builder.with_header(
-                        http::header::AUTHORIZATION.as_str(),
-                        authorization.value().await?,
-                    );
-                }
-                let (stream, _) = tokio_tungstenite::connect_async(builder).await?;
+                let stream = connect(&uri, authorization.as_ref(), tls.as_ref()).await?;
                 hyper::server::conn::http2::Builder::new(hyper_util::rt::TokioExecutor::new())
                     .keep_alive_interval(keep_alive_interval)
                     .timer(hyper_util::rt::TokioTimer::new())
@@ -84,14 +129,73 @@ pub(super) async fn serve(
     Ok(())
 }
 
+pub(super) fn check(config: &Config) -> Result<(), Error> {
+    let Config::V1 { load, .. } = config;
+    load.check()
+}
+
+async fn connect(
+    uri: &http::Uri,
+    authorization: Option<&config::Authorization>,
+    tls: Option<&config::Tls>,
+) -> Result<
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
+    Error,
+> {
+    #[derive(Debug, thiserror::Error)]
+    #[error("missing host in {0}")]
+    struct MissingHostError(http::Uri);
+
+    let server_name = tls.and_then(|tls| tls.server_name.as_ref());
+    let mut request_uri = uri.clone();
+    if let Some(server_name) = server_name {
+        client::set_host(&mut request_uri, server_name)?;
+    }
+    let mut builder = tokio_tungstenite::tungstenite::ClientRequestBuilder::new(request_uri);
+    if let Some(authorization) = authorization {
+        let (name, value) = authorization.header().await?;
+        builder = builder.with_header(name.as_str(), value.to_str()?);
+    }
+    let connector = tls
+        .map(|tls| {
+            Ok::<_, Error>(tokio_tungstenite::Connector::Rustls(Arc::new(
+                tls::client_config(tls)?,
+            )))
+        })
+        .transpose()?;
+
+    let (stream, _) = if server_name.is_some() {
+        let host = uri
+            .host()
+            .ok_or_else(|| MissingHostError(uri.clone()))?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+            Some("ws") => 80,
+            _ => 443,
+        });
+        let stream = tokio::net::TcpStream::connect((host, port)).await?;
+        tokio_tungstenite::client_async_tls_with_config(builder, stream, None, connector).await?
+    } else {
+        tokio_tungstenite::connect_async_tls_with_config(builder, None, false, connector).await?
+    };
+    Ok(stream)
+}
+
 #[derive(Clone)]
 struct State {
     keep_alive_interval: Duration,
+    label_routes: Arc<[LabelRoute]>,
+    lora_adapters: Arc<[LoraAdapter]>,
+    lora_loads: Arc<Mutex<HashMap<(uuid::Uuid, String), Arc<tokio::sync::OnceCell<()>>>>>,
+    discover_lora_adapters: bool,
+    model_status: bool,
+    load: super::Load,
     rx: Receiver,
 }
 
 async fn health(extract::State(state): extract::State<State>) -> http::StatusCode {
-    if state.rx.borrow().is_some() {
+    if state.rx.borrow().is_some() && config::stale_paths().is_empty() {
         http::StatusCode::OK
     } else {
         http::StatusCode::SERVICE_UNAVAILABLE
@@ -102,18 +206,34 @@ async fn list_models(
     extract::State(state): extract::State<State>,
 ) -> Result<axum::Json<schemas::List<schemas::Model>>, http::StatusCode> {
     if let Some((_, endpoints)) = state.rx.borrow().clone() {
-        let data = endpoints
-            .iter()
-            .flat_map(|endpoint| &endpoint.providers)
-            .flat_map(|provider| &provider.models)
-            .cloned()
-            .collect();
+        let data = super::list_models(
+            endpoints.iter().flat_map(|endpoint| &endpoint.providers),
+            state.model_status,
+        );
         Ok(axum::Json(schemas::List { data }))
     } else {
         Err(http::StatusCode::SERVICE_UNAVAILABLE)
     }
 }
 
+async fn get_model(
+    extract::State(state): extract::State<State>,
+    extract::Path(model_id): extract::Path<String>,
+) -> Result<axum::Json<schemas::Model>, http::StatusCode> {
+    if let Some((_, endpoints)) = state.rx.borrow().clone() {
+        super::list_models(
+            endpoints.iter().flat_map(|endpoint| &endpoint.providers),
+            state.model_status,
+        )
+        .into_iter()
+        .find(|model| model.id == model_id)
+        .map(axum::Json)
+        .ok_or(http::StatusCode::NOT_FOUND)
+    } else {
+        Err(http::StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
 async fn stream_providers(
     extract::State(state): extract::State<State>,
 ) -> axum::response::Sse<impl futures::Stream<Item = Result<axum::response::sse::Event, axum::Error>>>
@@ -129,8 +249,13 @@ async fn stream_providers(
                 if let Some((_, endpoints)) = self.stream.next().await? {
                     let mut providers = endpoints
                         .iter()
-                        .flat_map(|endpoint| &endpoint.providers)
-                        .cloned()
+                        .flat_map(|endpoint| {
+                            endpoint.providers.iter().map(|provider| {
+                                let mut provider = provider.clone();
+                                provider.labels.extend(endpoint.metadata.labels.clone());
+                                provider
+                            })
+                        })
                         .collect::<Vec<_>>();
                     providers.sort_unstable_by_key(|provider| provider.id);
                     providers.dedup_by_key(|provider| provider.id);
@@ -158,18 +283,21 @@ async fn fallback(
     extract::State(state): extract::State<State>,
     request: http::Request<axum::body::Body>,
 ) -> Result<http::Response<client::Body>, axum::response::Response> {
-    let (request, model_id) = if let Some(value) = request.headers().get(header::MODEL_ID)
+    let (request, model_id, max_tokens) = if let Some(value) =
+        request.headers().get(header::MODEL_ID)
         && let Ok(value) = value
             .to_str()
             .inspect_err(|e| tracing::warn!(warn = e.to_string()))
     {
         let value = value.to_owned();
         let request = request.map(http_body_util::BodyExt::boxed_unsync);
-        (request, value)
+        (request, value, None)
     } else {
         #[derive(serde::Deserialize)]
         struct Body {
             model: String,
+            max_tokens: Option<u64>,
+            max_completion_tokens: Option<u64>,
         }
 
         let (mut parts, body) =
@@ -178,7 +306,11 @@ async fn fallback(
                 &(),
             )
             .await?;
-        let Body { model } = serde_json::from_slice(&body).map_err(|e| {
+        let Body {
+            model,
+            max_tokens,
+            max_completion_tokens,
+        } = serde_json::from_slice(&body).map_err(|e| {
             tracing::warn!(warn = e.to_string());
             http::StatusCode::BAD_REQUEST.into_response()
         })?;
@@ -195,7 +327,7 @@ async fn fallback(
                 .map_err(|e| match e {})
                 .boxed_unsync(),
         );
-        (request, model)
+        (request, model, max_completion_tokens.or(max_tokens))
     };
 
     let (_, endpoints) = state
@@ -203,46 +335,312 @@ async fn fallback(
         .borrow()
         .clone()
         .ok_or(http::StatusCode::SERVICE_UNAVAILABLE.into_response())?;
-    let endpoints = endpoints
-        .iter()
-        .flat_map(|endpoint| {
-            let model_id = &model_id;
-            endpoint.providers.iter().filter_map(move |provider| {
-                provider
-                    .models
-                    .iter()
-                    .any(|model| model.id == *model_id)
-                    .then_some((endpoint, provider))
+    state
+        .lora_loads
+        .lock()
+        .unwrap()
+        .retain(|(endpoint_id, model_id), _| {
+            endpoints.iter().any(|endpoint| {
+                endpoint.id == *endpoint_id
+                    && !endpoint
+                        .providers
+                        .iter()
+                        .flat_map(|provider| &provider.models)
+                        .any(|model| model.id == *model_id)
             })
-        })
-        .collect::<Vec<_>>();
+        });
+    let serving = |model_id: &str| {
+        endpoints
+            .iter()
+            .flat_map(|endpoint| {
+                endpoint.providers.iter().filter_map(move |provider| {
+                    provider
+                        .models
+                        .iter()
+                        .any(|model| model.id == model_id)
+                        .then_some((endpoint, provider))
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut endpoints = serving(&model_id);
+    let loaded = endpoints
+        .iter()
+        .map(|(endpoint, _)| endpoint.id)
+        .collect::<HashSet<_>>();
+    let (lora_adapter, discovered) = select_lora_adapter(
+        &state.lora_adapters,
+        state.discover_lora_adapters,
+        endpoints.iter().map(|(_, provider)| *provider),
+        &model_id,
+    )
+    .unzip();
+    if let Some(lora_adapter) = &lora_adapter {
+        for (endpoint, provider) in serving(&lora_adapter.base_model) {
+            if !loaded.contains(&endpoint.id) && (discovered == Some(false) || is_vllm(provider)) {
+                endpoints.push((endpoint, provider));
+            }
+        }
+    }
+    if let Some(max_tokens) = max_tokens
+        && let Some(max_model_len) = endpoints
+            .iter()
+            .flat_map(|(_, provider)| &provider.models)
+            .filter(|model| model.id == model_id)
+            .map(|model| model.max_model_len)
+            .collect::<Option<Vec<_>>>()
+            .and_then(|max_model_len| max_model_len.into_iter().max())
+        && max_tokens > max_model_len
+    {
+        let body = serde_json::json!({
+            "error": {
+                "message": format!(
+                    "`max_tokens` ({max_tokens}) exceeds the context length ({max_model_len})",
+                ),
+                "type": "invalid_request_error",
+                "param": "max_tokens",
+                "code": null,
+            },
+        });
+        return Err((http::StatusCode::BAD_REQUEST, axum::Json(body)).into_response());
+    }
+    for label_route in state.label_routes.iter() {
+        if let Some(value) = request.headers().get(label_route.header.as_str())
+            && let Ok(value) = value
+                .to_str()
+                .inspect_err(|e| tracing::warn!(warn = e.to_string()))
+        {
+            let matched = endpoints
+                .iter()
+                .copied()
+                .filter(|(endpoint, provider)| {
+                    endpoint
+                        .metadata
+                        .labels
+                        .get(&label_route.label)
+                        .or_else(|| provider.labels.get(&label_route.label))
+                        .is_some_and(|label| label == value)
+                })
+                .collect::<Vec<_>>();
+            if label_route.mode == LabelRouteMode::Select || !matched.is_empty() {
+                endpoints = matched;
+            }
+        }
+    }
+    if let Some(priority) = endpoints
+        .iter()
+        .map(|(endpoint, _)| endpoint.metadata.priority)
+        .min_by_key(|priority| (priority.is_none(), *priority))
+    {
+        endpoints.retain(|(endpoint, _)| endpoint.metadata.priority == priority);
+    }
+    if endpoints
+        .iter()
+        .any(|(endpoint, _)| loaded.contains(&endpoint.id))
+    {
+        endpoints.retain(|(endpoint, _)| loaded.contains(&endpoint.id));
+    }
+    let resident = |provider: &schemas::Provider| {
+        provider
+            .models
+            .iter()
+            .any(|model| model.id == model_id && model.loaded == Some(true))
+    };
+    if endpoints.iter().any(|(_, provider)| resident(provider)) {
+        endpoints.retain(|(_, provider)| resident(provider));
+    }
 
-    if endpoints.is_empty() {
-        Err(http::StatusCode::SERVICE_UNAVAILABLE.into_response())
-    } else {
-        let dist =
-            rand::distr::weighted::WeightedIndex::new(endpoints.iter().map(|(_, provider)| {
-                1. / (1.
-                    + provider
-                        .metrics
-                        .vllm_num_requests_waiting
-                        .unwrap_or_default() as f64)
-            }))
-            .map_err(|e| {
-                tracing::warn!(error = e.to_string());
-                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            })?;
+    let mut load_failed = false;
+    let endpoint = loop {
+        if endpoints.is_empty() {
+            let status = if load_failed {
+                http::StatusCode::BAD_GATEWAY
+            } else {
+                http::StatusCode::SERVICE_UNAVAILABLE
+            };
+            return Err(status.into_response());
+        }
+        let dist = rand::distr::weighted::WeightedIndex::new(endpoints.iter().map(
+            |(endpoint, provider)| {
+                f64::from(endpoint.metadata.weight.max(1))
+                    / (1. + state.load.value(provider.metrics.model(&model_id)))
+            },
+        ))
+        .map_err(|e| {
+            tracing::warn!(error = e.to_string());
+            http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })?;
 
         let index = dist.sample(&mut rand::rng());
-        let (endpoint, _) = &endpoints[index];
-        let response = endpoint
-            .client
-            .send(request)
-            .map_err(|e| {
-                tracing::warn!(error = e.to_string());
-                http::StatusCode::BAD_GATEWAY.into_response()
+        let (endpoint, _) = endpoints[index];
+        tracing::debug!(
+            endpoint.id = endpoint.id.to_string(),
+            endpoint.labels = ?endpoint.metadata.labels,
+        );
+        if let Some(lora_adapter) = &lora_adapter
+            && !loaded.contains(&endpoint.id)
+        {
+            let load = state
+                .lora_loads
+                .lock()
+                .unwrap()
+                .entry((endpoint.id, lora_adapter.name.clone()))
+                .or_default()
+                .clone();
+            if load
+                .get_or_try_init(|| load_lora_adapter(&endpoint.client, lora_adapter))
+                .await
+                .is_err()
+            {
+                endpoints.swap_remove(index);
+                load_failed = true;
+                continue;
+            }
+        }
+        break endpoint;
+    };
+    let response = endpoint
+        .client
+        .send(request)
+        .map_err(|e| {
+            tracing::warn!(error = e.to_string());
+            http::StatusCode::BAD_GATEWAY.into_response()
+        })
+        .await?;
+    Ok(response)
+}
+
+fn is_vllm(provider: &schemas::Provider) -> bool {
+    provider.protocol.as_deref() == Some("vllm")
+}
+
+fn select_lora_adapter<'a, I>(
+    lora_adapters: &[LoraAdapter],
+    discover_lora_adapters: bool,
+    providers: I,
+    model_id: &str,
+) -> Option<(LoraAdapter, bool)>
+where
+    I: IntoIterator<Item = &'a schemas::Provider>,
+{
+    if let Some(lora_adapter) = lora_adapters
+        .iter()
+        .find(|lora_adapter| lora_adapter.name == model_id)
+    {
+        Some((lora_adapter.clone(), false))
+    } else if discover_lora_adapters {
+        providers
+            .into_iter()
+            .filter(|provider| is_vllm(provider))
+            .flat_map(|provider| &provider.models)
+            .filter(|model| model.id == model_id)
+            .find_map(|model| {
+                let lora_adapter = LoraAdapter {
+                    name: model.id.clone(),
+                    path: model.root.clone()?,
+                    base_model: model.parent.clone()?,
+                };
+                Some((lora_adapter, true))
             })
-            .await?;
-        Ok(response)
+    } else {
+        None
+    }
+}
+
+#[tracing::instrument(err(level = tracing::Level::WARN), skip(client))]
+async fn load_lora_adapter(
+    client: &client::Client,
+    lora_adapter: &LoraAdapter,
+) -> Result<(), Error> {
+    #[derive(Debug, thiserror::Error)]
+    #[error("{0:?}")]
+    struct StatusError(http::Response<bytes::Bytes>);
+
+    let body = serde_json::to_vec(&serde_json::json!({
+        "lora_name": lora_adapter.name,
+        "lora_path": lora_adapter.path,
+    }))?;
+    let request = http::Request::post("/v1/load_lora_adapter")
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(http_body_util::Full::new(bytes::Bytes::from(body)))?;
+    let response = client.send(request).await?;
+    let (parts, body) = response.into_parts();
+    if parts.status.is_success() {
+        Ok(())
+    } else {
+        let body = body.collect().await?.to_bytes();
+        Err(StatusError(http::Response::from_parts(parts, body)).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoraAdapter;
+    use std::collections::BTreeMap;
+
+    fn provider(
+        protocol: Option<&str>,
+        models: &[(&str, Option<&str>, Option<&str>)],
+    ) -> schemas::Provider {
+        schemas::Provider {
+            id: uuid::Uuid::new_v4(),
+            models: models
+                .iter()
+                .map(|(id, root, parent)| {
+                    let mut model = schemas::Model::new((*id).to_owned());
+                    model.root = root.map(str::to_owned);
+                    model.parent = parent.map(str::to_owned);
+                    model
+                })
+                .collect(),
+            metrics: schemas::Metrics::default(),
+            labels: BTreeMap::new(),
+            protocol: protocol.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_select_lora_adapter() {
+        let configured = LoraAdapter {
+            name: "foo".to_owned(),
+            path: "/adapters/foo".to_owned(),
+            base_model: "base".to_owned(),
+        };
+        let discovered = LoraAdapter {
+            name: "foo".to_owned(),
+            path: "/probed/foo".to_owned(),
+            base_model: "base".to_owned(),
+        };
+        let vllm = provider(
+            Some("vllm"),
+            &[
+                ("base", Some("/models/base"), None),
+                ("foo", Some("/probed/foo"), Some("base")),
+            ],
+        );
+        let sglang = provider(None, &[("foo", Some("/probed/foo"), Some("base"))]);
+
+        assert_eq!(
+            super::select_lora_adapter(&[configured.clone()], false, [&vllm], "foo"),
+            Some((configured.clone(), false)),
+        );
+        assert_eq!(
+            super::select_lora_adapter(&[configured.clone()], true, [&vllm], "foo"),
+            Some((configured, false)),
+        );
+        assert_eq!(super::select_lora_adapter(&[], false, [&vllm], "foo"), None);
+        assert_eq!(
+            super::select_lora_adapter(&[], true, [&vllm], "foo"),
+            Some((discovered.clone(), true)),
+        );
+        assert_eq!(
+            super::select_lora_adapter(&[], true, [&sglang], "foo"),
+            None
+        );
+        assert_eq!(
+            super::select_lora_adapter(&[], true, [&sglang, &vllm], "foo"),
+            Some((discovered, true)),
+        );
+        assert_eq!(super::select_lora_adapter(&[], true, [&vllm], "base"), None);
     }
 }
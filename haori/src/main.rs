@@ -1,21 +1,37 @@
 mod backend;
+mod check;
 mod client;
 mod config;
 mod endpoint;
 mod frontend;
 mod header;
+mod tls;
 
 use clap::Parser;
 use futures::StreamExt;
+use std::future;
+use std::io::{self, Write};
 use std::path::PathBuf;
 use std::pin;
+use std::time::{Duration, SystemTime};
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
 
 #[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
     #[clap(flatten)]
     config: ConfigArgs,
+    #[clap(long, value_parser = humantime::parse_duration)]
+    config_watch_interval: Option<Duration>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    Check(check::Args),
+    Schema,
 }
 
 #[derive(clap::Args)]
@@ -27,49 +43,129 @@ struct ConfigArgs {
     config_path: Option<PathBuf>,
 }
 
-#[derive(Clone, Debug, serde::Deserialize)]
+#[derive(Clone, Debug, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct Config {
     frontends: Vec<frontend::Config>,
     backends: Vec<backend::Config>,
 }
 
+impl ConfigArgs {
+    async fn load(&self) -> Result<Config, Error> {
+        let mut config: serde_json::Value = match self {
+            Self {
+                config: Some(config),
+                config_path: None,
+            } => serde_json::from_str(config)?,
+            Self {
+                config: None,
+                config_path: Some(config_path),
+            } => config::parse(config_path, &tokio::fs::read(config_path).await?)?,
+            _ => unreachable!(),
+        };
+        config::interpolate(&mut config)?;
+        let config: Config = serde_json::from_value(config)?;
+        config.check()?;
+        Ok(config)
+    }
+
+    async fn modified(&self) -> Option<SystemTime> {
+        let config_path = self.config_path.as_ref()?;
+        let metadata = tokio::fs::metadata(config_path).await.ok()?;
+        metadata.modified().ok()
+    }
+
+    async fn watch(
+        &self,
+        interval: Option<Duration>,
+        frontends_tx: tokio::sync::watch::Sender<Vec<frontend::Config>>,
+        backends_tx: tokio::sync::watch::Sender<Vec<backend::Config>>,
+    ) -> Result<(), Error> {
+        let mut signal = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+        let mut interval = interval.map(misc::time::interval);
+        let mut modified = self.modified().await;
+        loop {
+            let tick = async {
+                if let Some(interval) = &mut interval {
+                    interval.tick().await
+                } else {
+                    future::pending().await
+                }
+            };
+            tokio::select! {
+                _ = signal.recv() => (),
+                () = tick => {
+                    let modified_next = self.modified().await;
+                    if modified_next == modified {
+                        continue;
+                    }
+                    modified = modified_next;
+                }
+            }
+
+            let config = async {
+                let config = self.load().await?;
+                backend::validate(&config.backends)?;
+                Ok::<_, Error>(config)
+            };
+            match config.await {
+                Ok(config) => {
+                    tracing::info!("reload config");
+                    frontends_tx.send_replace(config.frontends);
+                    backends_tx.send_replace(config.backends);
+                }
+                Err(e) => tracing::error!(error = e.to_string()),
+            }
+        }
+    }
+}
+
+impl Config {
+    fn check(&self) -> Result<(), Error> {
+        for config in &self.frontends {
+            config.check()?;
+        }
+        for config in &self.backends {
+            config.check()?;
+        }
+        Ok(())
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     tracing_subscriber::fmt::init();
 
     let args = Args::parse();
-    let config: Config = match &args.config {
-        ConfigArgs {
-            config: Some(config),
-            config_path: None,
-        } => serde_json::from_str(config)?,
-        ConfigArgs {
-            config: None,
-            config_path: Some(config_path),
-        } => {
-            let config = tokio::fs::read(config_path).await?;
-            if config_path
-                .extension()
-                .is_some_and(|extension| extension == "toml")
-            {
-                toml::from_slice(&config)?
-            } else {
-                serde_json::from_slice(&config)?
+    if let Some(command) = args.command {
+        return match command {
+            Command::Check(args) => check::check(args).await,
+            Command::Schema => {
+                let mut stdout = io::stdout().lock();
+                serde_json::to_writer_pretty(&mut stdout, &schemars::schema_for!(Config))?;
+                writeln!(stdout)?;
+                Ok(())
             }
-        }
-        _ => unreachable!(),
-    };
+        };
+    }
+    let config = args.config.load().await?;
 
+    let (frontends_tx, frontends_rx) = tokio::sync::watch::channel(config.frontends);
+    let (backends_tx, backends_rx) = tokio::sync::watch::channel(config.backends);
     let (tx, rx) = tokio::sync::watch::channel(None);
-    futures::future::try_join(frontend::serve(config.frontends, rx), async move {
-        let stream = backend::watch(config.backends).await?.enumerate();
-        let mut stream = pin::pin!(stream);
-        while let Some((version, endpoints)) = stream.next().await {
-            tx.send(Some((version, endpoints.into())))?;
-        }
-        Ok(())
-    })
+    futures::future::try_join3(
+        args.config
+            .watch(args.config_watch_interval, frontends_tx, backends_tx),
+        frontend::serve(frontends_rx, rx),
+        async move {
+            let stream = backend::watch(backends_rx)?.enumerate();
+            let mut stream = pin::pin!(stream);
+            while let Some((version, endpoints)) = stream.next().await {
+                tx.send(Some((version, endpoints.into())))?;
+            }
+            Ok(())
+        },
+    )
     .await?;
     Ok(())
 }
@@ -0,0 +1,398 @@
+use crate::{Error, config};
+use futures::{FutureExt, StreamExt};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::fs;
+use std::future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HANDSHAKES: usize = 1024;
+
+pub fn client_config(config: &config::Tls) -> Result<rustls::ClientConfig, Error> {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(protocol_versions(config.min_version))?;
+    let builder = if config.insecure {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(InsecureVerifier(provider)))
+    } else {
+        let mut root_store = rustls::RootCertStore::empty();
+        let rustls_native_certs::CertificateResult { certs, errors, .. } =
+            rustls_native_certs::load_native_certs();
+        for e in errors {
+            tracing::warn!(error = e.to_string());
+        }
+        root_store.add_parsable_certificates(certs);
+        for path in &config.ca_certificates {
+            for certificate in CertificateDer::pem_file_iter(path)? {
+                root_store.add(certificate?)?;
+            }
+        }
+        builder.with_root_certificates(root_store)
+    };
+    let config = if let Some(identity) = &config.identity {
+        let cert_chain =
+            CertificateDer::pem_file_iter(&identity.certificate)?.collect::<Result<Vec<_>, _>>()?;
+        let key_der = PrivateKeyDer::from_pem_file(&identity.key)?;
+        builder.with_client_auth_cert(cert_chain, key_der)?
+    } else {
+        builder.with_no_client_auth()
+    };
+    Ok(config)
+}
+
+fn protocol_versions(
+    min_version: Option<config::TlsVersion>,
+) -> &'static [&'static rustls::SupportedProtocolVersion] {
+    static TLS13: &[&rustls::SupportedProtocolVersion] = &[&rustls::version::TLS13];
+
+    match min_version {
+        None | Some(config::TlsVersion::Tls1_2) => rustls::ALL_VERSIONS,
+        Some(config::TlsVersion::Tls1_3) => TLS13,
+    }
+}
+
+#[derive(Debug)]
+struct InsecureVerifier(Arc<rustls::crypto::CryptoProvider>);
+
+impl rustls::client::danger::ServerCertVerifier for InsecureVerifier {
+    fn verify_server_cert(
+        &self,
+        _: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &rustls::pki_types::ServerName<'_>,
+        _: &[u8],
+        _: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+pub fn server_config(config: &config::TlsBind) -> Result<rustls::ServerConfig, Error> {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(rustls::ALL_VERSIONS)?;
+    let builder = if config.client_ca_certificates.is_empty() {
+        builder.with_no_client_auth()
+    } else {
+        let mut root_store = rustls::RootCertStore::empty();
+        for path in &config.client_ca_certificates {
+            for certificate in CertificateDer::pem_file_iter(path)? {
+                root_store.add(certificate?)?;
+            }
+        }
+        let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(
+            Arc::new(root_store),
+            provider.clone(),
+        )
+        .build()?;
+        builder.with_client_cert_verifier(verifier)
+    };
+    let resolver = CertResolver::new(config.certificate.clone(), config.key.clone(), provider)?;
+    let mut config = builder.with_cert_resolver(Arc::new(resolver));
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+#[derive(Debug)]
+struct CertResolver {
+    certificate: PathBuf,
+    key: PathBuf,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+    state: Mutex<CertState>,
+}
+
+#[derive(Debug)]
+struct CertState {
+    certified_key: Arc<rustls::sign::CertifiedKey>,
+    modified: (SystemTime, SystemTime),
+    checked: Instant,
+}
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+impl CertResolver {
+    fn new(
+        certificate: PathBuf,
+        key: PathBuf,
+        provider: Arc<rustls::crypto::CryptoProvider>,
+    ) -> Result<Self, Error> {
+        let modified = modified(&certificate, &key)?;
+        let certified_key = load(&certificate, &key, &provider)?;
+        Ok(Self {
+            certificate,
+            key,
+            provider,
+            state: Mutex::new(CertState {
+                certified_key,
+                modified,
+                checked: Instant::now(),
+            }),
+        })
+    }
+}
+
+impl rustls::server::ResolvesServerCert for CertResolver {
+    fn resolve(
+        &self,
+        _: rustls::server::ClientHello<'_>,
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        let mut state = self.state.lock().unwrap();
+        if state.checked.elapsed() >= CHECK_INTERVAL {
+            state.checked = Instant::now();
+            match modified(&self.certificate, &self.key) {
+                Ok(modified) if modified != state.modified => {
+                    match load(&self.certificate, &self.key, &self.provider) {
+                        Ok(certified_key) => {
+                            tracing::info!(certificate = ?self.certificate, "reloaded");
+                            state.certified_key = certified_key;
+                            state.modified = modified;
+                        }
+                        Err(e) => tracing::warn!(error = e.to_string()),
+                    }
+                }
+                Ok(_) => (),
+                Err(e) => tracing::warn!(error = e.to_string()),
+            }
+        }
+        Some(state.certified_key.clone())
+    }
+}
+
+fn modified(certificate: &Path, key: &Path) -> io::Result<(SystemTime, SystemTime)> {
+    Ok((
+        fs::metadata(certificate)?.modified()?,
+        fs::metadata(key)?.modified()?,
+    ))
+}
+
+fn load(
+    certificate: &Path,
+    key: &Path,
+    provider: &rustls::crypto::CryptoProvider,
+) -> Result<Arc<rustls::sign::CertifiedKey>, Error> {
+    let cert_chain = CertificateDer::pem_file_iter(certificate)?.collect::<Result<Vec<_>, _>>()?;
+    let key = provider
+        .key_provider
+        .load_private_key(PrivateKeyDer::from_pem_file(key)?)?;
+    Ok(Arc::new(rustls::sign::CertifiedKey::new(cert_chain, key)))
+}
+
+pub async fn accept<S>(acceptor: &tokio_rustls::TlsAcceptor, stream: S) -> io::Result<TlsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    Ok(TlsStream {
+        inner: acceptor.accept(stream).await?,
+    })
+}
+
+async fn accept_timeout<S>(
+    acceptor: &tokio_rustls::TlsAcceptor,
+    stream: S,
+) -> io::Result<TlsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, accept(acceptor, stream)).await?
+}
+
+#[pin_project::pin_project]
+pub struct TlsStream<S> {
+    #[pin]
+    inner: tokio_rustls::server::TlsStream<S>,
+}
+
+impl<S> TlsStream<S> {
+    pub fn get_ref(&self) -> (&S, &rustls::ServerConnection) {
+        self.inner.get_ref()
+    }
+
+    pub fn peer_identity(&self) -> Option<String> {
+        let (_, connection) = self.get_ref();
+        let certificate = connection.peer_certificates()?.first()?;
+        let certificate = webpki::EndEntityCert::try_from(certificate)
+            .inspect_err(|e| tracing::warn!(error = e.to_string()))
+            .ok()?;
+        let identity = certificate
+            .valid_uri_names()
+            .next()
+            .or_else(|| certificate.valid_dns_names().next())?;
+        Some(identity.to_owned())
+    }
+}
+
+impl<S> AsyncRead for TlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.project().inner.poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for TlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+impl<S> tonic::transport::server::Connected for TlsStream<S>
+where
+    S: tonic::transport::server::Connected,
+{
+    type ConnectInfo = S::ConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.get_ref().0.connect_info()
+    }
+}
+
+pub struct Listener<L>
+where
+    L: axum::serve::Listener,
+{
+    listener: L,
+    acceptor: tokio_rustls::TlsAcceptor,
+    handshakes: futures::stream::FuturesUnordered<
+        futures::future::BoxFuture<'static, (io::Result<TlsStream<L::Io>>, L::Addr)>,
+    >,
+}
+
+impl<L> Listener<L>
+where
+    L: axum::serve::Listener,
+{
+    pub fn new(listener: L, acceptor: tokio_rustls::TlsAcceptor) -> Self {
+        Self {
+            listener,
+            acceptor,
+            handshakes: futures::stream::FuturesUnordered::new(),
+        }
+    }
+}
+
+impl<L> axum::serve::Listener for Listener<L>
+where
+    L: axum::serve::Listener,
+{
+    type Io = TlsStream<L::Io>;
+    type Addr = L::Addr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            tokio::select! {
+                (stream, addr) = self.listener.accept(), if self.handshakes.len() < MAX_HANDSHAKES => {
+                    let acceptor = self.acceptor.clone();
+                    self.handshakes
+                        .push(async move { (accept_timeout(&acceptor, stream).await, addr) }.boxed());
+                }
+                Some((stream, addr)) = self.handshakes.next() => match stream {
+                    Ok(stream) => break (stream, addr),
+                    Err(e) => tracing::warn!(error = e.to_string()),
+                },
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.listener.local_addr()
+    }
+}
+
+pub fn incoming<S, T, E>(
+    stream: S,
+    acceptor: tokio_rustls::TlsAcceptor,
+) -> impl futures::Stream<Item = Result<TlsStream<T>, E>>
+where
+    S: futures::Stream<Item = Result<T, E>>,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    stream
+        .map(move |stream| {
+            let acceptor = acceptor.clone();
+            async move {
+                match stream {
+                    Ok(stream) => accept_timeout(&acceptor, stream)
+                        .await
+                        .inspect_err(|e| tracing::warn!(error = e.to_string()))
+                        .ok()
+                        .map(Ok),
+                    Err(e) => Some(Err(e)),
+                }
+            }
+        })
+        .buffer_unordered(MAX_HANDSHAKES)
+        .filter_map(future::ready)
+}
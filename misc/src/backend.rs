@@ -1,11 +1,12 @@
 use futures::{FutureExt, StreamExt};
 
-pub fn discover_and_probe<'a, I, D, P, T, E, F, U>(
-    discover_streams: I,
+pub fn discover_and_probe<'a, S, D, P, T, E, F, U>(
+    source_stream: S,
     f: F,
 ) -> impl futures::Stream<Item = Vec<U>> + 'a
 where
-    I: IntoIterator<Item = D>,
+    S: futures::Stream + Unpin + 'a,
+    S::Item: IntoIterator<Item = (D, futures::future::AbortRegistration)>,
     D: futures::Stream + Unpin + 'a,
     D::Item: IntoIterator<Item = (P, futures::future::AbortRegistration)>,
     P: futures::Stream<Item = Result<T, E>> + Unpin + 'a,
@@ -13,14 +14,16 @@ where
     E: 'a,
     F: FnMut(&T) -> U + 'a,
 {
-    struct State<D, P, T, E, F> {
-        streams: Vec<Stream<D, P, T, E>>,
+    struct State<S, D, P, T, E, F> {
+        streams: Vec<Stream<S, D, P, T, E>>,
         is_ready: bool,
         f: F,
     }
 
-    impl<D, P, T, E, F, U> State<D, P, T, E, F>
+    impl<S, D, P, T, E, F, U> State<S, D, P, T, E, F>
     where
+        S: futures::Stream + Unpin,
+        S::Item: IntoIterator<Item = (D, futures::future::AbortRegistration)>,
         D: futures::Stream + Unpin,
         D::Item: IntoIterator<Item = (P, futures::future::AbortRegistration)>,
         P: futures::Stream<Item = Result<T, E>> + Unpin,
@@ -28,6 +31,7 @@ where
     {
         async fn next(&mut self) -> Option<Vec<U>> {
             loop {
+                self.streams.retain(|stream| !stream.is_aborted());
                 if self.streams.is_empty() {
                     break None;
                 }
@@ -40,7 +44,9 @@ where
                 }
 
                 if self.streams.iter().all(|stream| match stream {
-                    Stream::Discover { is_ready, .. } => *is_ready,
+                    Stream::Source { is_ready, .. } | Stream::Discover { is_ready, .. } => {
+                        *is_ready
+                    }
                     Stream::Probe { item, .. } => item.is_some(),
                 }) {
                     self.is_ready = true;
@@ -54,6 +60,7 @@ where
                                 item: Some(Ok(item)),
                                 ..
                             } = stream
+                                && !stream.is_aborted()
                             {
                                 Some((self.f)(item))
                             } else {
@@ -68,39 +75,77 @@ where
     }
 
     let state = State {
-        streams: discover_streams
-            .into_iter()
-            .map(|discover_stream| Stream::Discover {
-                stream: discover_stream,
-                is_ready: false,
-            })
-            .collect(),
+        streams: vec![Stream::Source {
+            stream: source_stream,
+            is_ready: false,
+        }],
         is_ready: false,
         f,
     };
     futures::stream::unfold(state, async |mut state| Some((state.next().await?, state)))
 }
 
-enum Stream<D, P, T, E> {
+enum Stream<S, D, P, T, E> {
+    Source {
+        stream: S,
+        is_ready: bool,
+    },
     Discover {
-        stream: D,
+        stream: futures::future::Abortable<D>,
+        abort_handle: futures::future::AbortHandle,
         is_ready: bool,
     },
     Probe {
         stream: futures::future::Abortable<P>,
+        discover: futures::future::AbortHandle,
         item: Option<Result<T, E>>,
     },
 }
 
-impl<D, P, T, E> Stream<D, P, T, E>
+impl<S, D, P, T, E> Stream<S, D, P, T, E>
 where
+    S: futures::Stream + Unpin,
+    S::Item: IntoIterator<Item = (D, futures::future::AbortRegistration)>,
     D: futures::Stream + Unpin,
     D::Item: IntoIterator<Item = (P, futures::future::AbortRegistration)>,
     P: futures::Stream<Item = Result<T, E>> + Unpin,
 {
+    fn is_aborted(&self) -> bool {
+        match self {
+            Self::Source { .. } => false,
+            Self::Discover { stream, .. } => stream.is_aborted(),
+            Self::Probe {
+                stream, discover, ..
+            } => stream.is_aborted() || discover.is_aborted(),
+        }
+    }
+
     fn next(&mut self) -> impl Future<Output = Option<Vec<Self>>> + Unpin + '_ {
         match self {
-            Self::Discover { stream, is_ready } => stream
+            Self::Source { stream, is_ready } => stream
+                .next()
+                .map(move |discover_streams| {
+                    let streams = discover_streams?
+                        .into_iter()
+                        .map(|(discover_stream, abort_registration)| Self::Discover {
+                            abort_handle: abort_registration.handle(),
+                            stream: futures::future::Abortable::new(
+                                discover_stream,
+                                abort_registration,
+                            ),
+                            is_ready: false,
+                        })
+                        .collect();
+                    *is_ready = true;
+                    Some(streams)
+                })
+                .left_future()
+                .left_future(),
+            Self::Discover {
+                stream,
+                abort_handle,
+                is_ready,
+            } => stream
                 .next()
                 .map(move |probe_streams| {
                     let streams = probe_streams?
@@ -110,14 +155,16 @@ where
                                 probe_stream,
                                 abort_registration,
                             ),
+                            discover: abort_handle.clone(),
                             item: None,
                         })
                         .collect();
                     *is_ready = true;
                     Some(streams)
                 })
+                .right_future()
                 .left_future(),
-            Stream::Probe { stream, item } => stream
+            Stream::Probe { stream, item, .. } => stream
                 .next()
                 .map(move |item_next| {
                     *item = Some(item_next?);
@@ -1,32 +1,521 @@
 use nom::Finish;
+use std::collections::BTreeMap;
+use std::time::Duration;
 
-pub fn parse_vllm(data: &str) -> Result<schemas::Metrics, nom::error::Error<String>> {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aggregation {
+    Sum,
+    Max,
+    Avg,
+    Quantile(f64),
+}
+
+pub struct Mapping<'a> {
+    pub metricname: &'a str,
+    pub labels: &'a BTreeMap<String, String>,
+    pub aggregation: Aggregation,
+    pub group_by: Option<&'a str>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Aggregated {
+    pub value: Option<f64>,
+    pub groups: BTreeMap<String, f64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Histograms(BTreeMap<(usize, Option<String>), Vec<(f64, f64)>>);
+
+impl Histograms {
+    fn delta(
+        &mut self,
+        key: (usize, Option<String>),
+        buckets: Vec<(f64, f64)>,
+    ) -> Option<Vec<(f64, f64)>> {
+        let previous = self.0.insert(key, buckets.clone())?;
+        if previous.len() != buckets.len() {
+            return None;
+        }
+        buckets
+            .into_iter()
+            .zip(previous)
+            .map(|((le, count), (le_prev, count_prev))| {
+                (le == le_prev && count >= count_prev).then_some((le, count - count_prev))
+            })
+            .collect()
+    }
+}
+
+const MODEL_NAME: &str = "model_name";
+
+type Field = (&'static str, Aggregation, fn(&mut schemas::Metrics, f64));
+
+pub fn parse_vllm(
+    data: &str,
+    histograms: &mut Histograms,
+) -> Result<schemas::Metrics, nom::error::Error<String>> {
+    parse_fields(
+        data,
+        histograms,
+        &[
+            (
+                "vllm:num_requests_running",
+                Aggregation::Sum,
+                |metrics, v| {
+                    metrics.vllm_num_requests_running = Some(v as u32);
+                },
+            ),
+            (
+                "vllm:num_requests_waiting",
+                Aggregation::Sum,
+                |metrics, v| {
+                    metrics.vllm_num_requests_waiting = Some(v as u32);
+                },
+            ),
+            (
+                "vllm:gpu_cache_usage_perc",
+                Aggregation::Max,
+                |metrics, v| {
+                    metrics.vllm_kv_cache_usage_perc = Some(v);
+                },
+            ),
+            (
+                "vllm:kv_cache_usage_perc",
+                Aggregation::Max,
+                |metrics, v| {
+                    metrics.vllm_kv_cache_usage_perc = Some(v);
+                },
+            ),
+            (
+                "vllm:num_preemptions_total",
+                Aggregation::Sum,
+                |metrics, v| {
+                    metrics.vllm_num_preemptions_total = Some(v as u64);
+                },
+            ),
+            (
+                "vllm:prompt_tokens_total",
+                Aggregation::Sum,
+                |metrics, v| {
+                    metrics.vllm_prompt_tokens_total = Some(v as u64);
+                },
+            ),
+            (
+                "vllm:generation_tokens_total",
+                Aggregation::Sum,
+                |metrics, v| {
+                    metrics.vllm_generation_tokens_total = Some(v as u64);
+                },
+            ),
+            (
+                "vllm:time_to_first_token_seconds",
+                Aggregation::Quantile(0.5),
+                |metrics, v| metrics.vllm_time_to_first_token_seconds_p50 = Some(v),
+            ),
+            (
+                "vllm:time_to_first_token_seconds",
+                Aggregation::Quantile(0.99),
+                |metrics, v| metrics.vllm_time_to_first_token_seconds_p99 = Some(v),
+            ),
+            (
+                "vllm:time_per_output_token_seconds",
+                Aggregation::Quantile(0.5),
+                |metrics, v| metrics.vllm_time_per_output_token_seconds_p50 = Some(v),
+            ),
+            (
+                "vllm:time_per_output_token_seconds",
+                Aggregation::Quantile(0.99),
+                |metrics, v| metrics.vllm_time_per_output_token_seconds_p99 = Some(v),
+            ),
+        ],
+    )
+}
+
+pub fn parse_sglang(data: &str) -> Result<schemas::Metrics, nom::error::Error<String>> {
+    parse_fields(
+        data,
+        &mut Histograms::default(),
+        &[
+            ("sglang:num_running_reqs", Aggregation::Sum, |metrics, v| {
+                metrics.vllm_num_requests_running = Some(v as u32);
+            }),
+            ("sglang:num_queue_reqs", Aggregation::Sum, |metrics, v| {
+                metrics.vllm_num_requests_waiting = Some(v as u32);
+            }),
+        ],
+    )
+}
+
+pub fn parse_tgi(data: &str) -> Result<schemas::Metrics, nom::error::Error<String>> {
+    parse_fields(
+        data,
+        &mut Histograms::default(),
+        &[
+            ("tgi_batch_current_size", Aggregation::Sum, |metrics, v| {
+                metrics.vllm_num_requests_running = Some(v as u32);
+            }),
+            ("tgi_queue_size", Aggregation::Sum, |metrics, v| {
+                metrics.vllm_num_requests_waiting = Some(v as u32);
+            }),
+            ("te_batch_current_size", Aggregation::Sum, |metrics, v| {
+                metrics.vllm_num_requests_running = Some(v as u32);
+            }),
+            ("te_queue_size", Aggregation::Sum, |metrics, v| {
+                metrics.vllm_num_requests_waiting = Some(v as u32);
+            }),
+        ],
+    )
+}
+
+fn parse_fields(
+    data: &str,
+    histograms: &mut Histograms,
+    fields: &[Field],
+) -> Result<schemas::Metrics, nom::error::Error<String>> {
+    let labels = BTreeMap::new();
+    let mappings = fields
+        .iter()
+        .map(|(metricname, aggregation, _)| Mapping {
+            metricname,
+            labels: &labels,
+            aggregation: *aggregation,
+            group_by: Some(MODEL_NAME),
+        })
+        .collect::<Vec<_>>();
+
+    let mut metrics = schemas::Metrics::default();
+    for ((_, _, set), aggregated) in fields.iter().zip(aggregate(data, &mappings, histograms)?) {
+        if let Some(v) = aggregated.value {
+            set(&mut metrics, v);
+        }
+        for (model, v) in aggregated.groups {
+            set(metrics.models.entry(model).or_default(), v);
+        }
+    }
+    Ok(metrics)
+}
+
+pub fn rates(metrics: &mut schemas::Metrics, previous: &schemas::Metrics, elapsed: Duration) {
+    let rate = |current: Option<u64>, previous: Option<u64>| {
+        let delta = current?.checked_sub(previous?)?;
+        (!elapsed.is_zero()).then(|| delta as f64 / elapsed.as_secs_f64())
+    };
+    metrics.vllm_num_preemptions_per_second = rate(
+        metrics.vllm_num_preemptions_total,
+        previous.vllm_num_preemptions_total,
+    );
+    metrics.vllm_prompt_tokens_per_second = rate(
+        metrics.vllm_prompt_tokens_total,
+        previous.vllm_prompt_tokens_total,
+    );
+    metrics.vllm_generation_tokens_per_second = rate(
+        metrics.vllm_generation_tokens_total,
+        previous.vllm_generation_tokens_total,
+    );
+    for (model, metrics) in &mut metrics.models {
+        if let Some(previous) = previous.models.get(model) {
+            rates(metrics, previous, elapsed);
+        }
+    }
+}
+
+pub fn aggregate(
+    data: &str,
+    mappings: &[Mapping<'_>],
+    histograms: &mut Histograms,
+) -> Result<Vec<Aggregated>, nom::error::Error<String>> {
     let mut data = data.replace("\r\n", "\n");
     data.push_str("# EOF\n");
     let (_, exposition) = openmetrics_nom::exposition(data.as_str())
         .finish()
         .map_err(nom::error::Error::<&str>::cloned)?;
     let (_, metricset) = &exposition.metricset;
-    let metrics = metricset
+
+    let mut matched = vec![Vec::new(); mappings.len()];
+    for (_, sample) in metricset
         .metricfamily
         .iter()
         .flat_map(|(_, metricfamily)| &metricfamily.metric)
         .flat_map(|(_, metric)| &metric.sample)
-        .fold(schemas::Metrics::default(), |mut metrics, (_, sample)| {
-            match sample.metricname {
-                "vllm:num_requests_running" => {
-                    if let Ok(v) = sample.number.parse::<f64>() {
-                        *metrics.vllm_num_requests_running.get_or_insert_default() += v as u32;
-                    }
+    {
+        let Ok(v) = sample.number.parse::<f64>() else {
+            continue;
+        };
+        let labels = sample
+            .labels
+            .iter()
+            .flat_map(|(_, labels)| &labels.label)
+            .map(|(_, label)| (label.label_name, label.escaped_string.to_string()))
+            .collect::<BTreeMap<_, _>>();
+        for (mapping, matched) in mappings.iter().zip(&mut matched) {
+            let le = if let Aggregation::Quantile(_) = mapping.aggregation {
+                if sample.metricname.strip_suffix("_bucket") != Some(mapping.metricname) {
+                    continue;
                 }
-                "vllm:num_requests_waiting" => {
-                    if let Ok(v) = sample.number.parse::<f64>() {
-                        *metrics.vllm_num_requests_waiting.get_or_insert_default() += v as u32;
-                    }
+                match labels.get("le").map(|le| le.parse::<f64>()) {
+                    Some(Ok(le)) => le,
+                    _ => continue,
                 }
-                _ => (),
+            } else if sample.metricname == mapping.metricname {
+                0.
+            } else {
+                continue;
+            };
+            if mapping
+                .labels
+                .iter()
+                .all(|(name, value)| labels.get(name.as_str()) == Some(value))
+            {
+                let group = mapping
+                    .group_by
+                    .and_then(|group_by| labels.get(group_by))
+                    .cloned();
+                matched.push((group, le, v));
+            }
+        }
+    }
+
+    let mut aggregated = Vec::with_capacity(mappings.len());
+    for (index, (mapping, matched)) in mappings.iter().zip(matched).enumerate() {
+        let mut groups = BTreeMap::<_, Vec<_>>::new();
+        for (group, le, v) in &matched {
+            if let Some(group) = group {
+                groups.entry(group.clone()).or_default().push((*le, *v));
+            }
+        }
+        let mut reduce_group = |group: Option<String>, matched: Vec<_>| match mapping.aggregation {
+            Aggregation::Quantile(q) => {
+                histogram_quantile(q, histograms.delta((index, group), merge_buckets(matched))?)
             }
-            metrics
+            aggregation => reduce(aggregation, matched),
+        };
+        aggregated.push(Aggregated {
+            value: reduce_group(
+                None,
+                matched.into_iter().map(|(_, le, v)| (le, v)).collect(),
+            ),
+            groups: groups
+                .into_iter()
+                .filter_map(|(group, matched)| {
+                    Some((group.clone(), reduce_group(Some(group), matched)?))
+                })
+                .collect(),
         });
-    Ok(metrics)
+    }
+    Ok(aggregated)
+}
+
+fn reduce(aggregation: Aggregation, matched: Vec<(f64, f64)>) -> Option<f64> {
+    let len = matched.len() as f64;
+    let values = matched.iter().map(|(_, v)| *v);
+    match aggregation {
+        Aggregation::Sum => values.reduce(|a, b| a + b),
+        Aggregation::Max => values.reduce(f64::max),
+        Aggregation::Avg => values.reduce(|a, b| a + b).map(|sum| sum / len),
+        Aggregation::Quantile(q) => histogram_quantile(q, matched),
+    }
+}
+
+fn merge_buckets(mut matched: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    matched.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    matched.dedup_by(|(le, count), (le_prev, count_prev)| {
+        if le == le_prev {
+            *count_prev += *count;
+            true
+        } else {
+            false
+        }
+    });
+    matched
+}
+
+fn histogram_quantile(q: f64, buckets: Vec<(f64, f64)>) -> Option<f64> {
+    let buckets = merge_buckets(buckets);
+    let &(le_max, total) = buckets.last()?;
+    if le_max != f64::INFINITY || total <= 0. {
+        return None;
+    }
+
+    let rank = q.clamp(0., 1.) * total;
+    let (mut le_prev, mut count_prev) = (0., 0.);
+    for (le, count) in buckets {
+        if count >= rank {
+            if le == f64::INFINITY {
+                return Some(le_prev);
+            } else if count == count_prev {
+                return Some(le);
+            } else {
+                return Some(le_prev + (le - le_prev) * (rank - count_prev) / (count - count_prev));
+            }
+        }
+        (le_prev, count_prev) = (le, count);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rates() {
+        let previous = schemas::Metrics {
+            vllm_num_preemptions_total: Some(4),
+            vllm_prompt_tokens_total: Some(100),
+            vllm_generation_tokens_total: Some(1000),
+            models: BTreeMap::from([(
+                "lora".to_owned(),
+                schemas::Metrics {
+                    vllm_prompt_tokens_total: Some(10),
+                    ..schemas::Metrics::default()
+                },
+            )]),
+            ..schemas::Metrics::default()
+        };
+        let mut metrics = schemas::Metrics {
+            vllm_num_preemptions_total: Some(5),
+            vllm_prompt_tokens_total: Some(300),
+            vllm_generation_tokens_total: Some(10),
+            models: BTreeMap::from([(
+                "lora".to_owned(),
+                schemas::Metrics {
+                    vllm_prompt_tokens_total: Some(30),
+                    ..schemas::Metrics::default()
+                },
+            )]),
+            ..schemas::Metrics::default()
+        };
+        super::rates(&mut metrics, &previous, Duration::from_secs(2));
+        assert_eq!(metrics.vllm_num_preemptions_per_second, Some(0.5));
+        assert_eq!(metrics.vllm_prompt_tokens_per_second, Some(100.));
+        assert_eq!(metrics.vllm_generation_tokens_per_second, None);
+        assert_eq!(
+            metrics.models["lora"].vllm_prompt_tokens_per_second,
+            Some(10.),
+        );
+    }
+
+    #[test]
+    fn test_histograms() {
+        let mut histograms = super::Histograms::default();
+        let key = (0, None);
+        assert_eq!(
+            histograms.delta(key.clone(), vec![(0.1, 10.), (f64::INFINITY, 20.)]),
+            None,
+        );
+        assert_eq!(
+            histograms.delta(key.clone(), vec![(0.1, 15.), (f64::INFINITY, 30.)]),
+            Some(vec![(0.1, 5.), (f64::INFINITY, 10.)]),
+        );
+        assert_eq!(
+            histograms.delta(key.clone(), vec![(0.1, 1.), (f64::INFINITY, 2.)]),
+            None,
+        );
+        assert_eq!(
+            histograms.delta(key, vec![(0.1, 1.), (f64::INFINITY, 4.)]),
+            Some(vec![(0.1, 0.), (f64::INFINITY, 2.)]),
+        );
+    }
+
+    #[test]
+    fn test_aggregate() {
+        fn data(buckets: [u32; 3]) -> String {
+            format!(
+                r#"# TYPE vllm:num_requests_running gauge
+vllm:num_requests_running{{engine="0",model_name="a"}} 2.0
+vllm:num_requests_running{{engine="1",model_name="a"}} 3.0
+vllm:num_requests_running{{engine="0",model_name="b"}} 4.0
+# TYPE vllm:kv_cache_usage_perc gauge
+vllm:kv_cache_usage_perc{{engine="0",model_name="a"}} 0.25
+vllm:kv_cache_usage_perc{{engine="1",model_name="a"}} 0.75
+# TYPE vllm:time_to_first_token_seconds histogram
+vllm:time_to_first_token_seconds_bucket{{le="0.5",model_name="a"}} {}
+vllm:time_to_first_token_seconds_bucket{{le="1.0",model_name="a"}} {}
+vllm:time_to_first_token_seconds_bucket{{le="+Inf",model_name="a"}} {}
+vllm:time_to_first_token_seconds_sum{{model_name="a"}} 10.0
+vllm:time_to_first_token_seconds_count{{model_name="a"}} {}
+"#,
+                buckets[0], buckets[1], buckets[2], buckets[2],
+            )
+        }
+
+        let model_a = BTreeMap::from([("model_name".to_owned(), "a".to_owned())]);
+        let none = BTreeMap::new();
+        let mappings = [
+            super::Mapping {
+                metricname: "vllm:num_requests_running",
+                labels: &model_a,
+                aggregation: super::Aggregation::Sum,
+                group_by: None,
+            },
+            super::Mapping {
+                metricname: "vllm:num_requests_running",
+                labels: &none,
+                aggregation: super::Aggregation::Max,
+                group_by: Some("model_name"),
+            },
+            super::Mapping {
+                metricname: "vllm:kv_cache_usage_perc",
+                labels: &none,
+                aggregation: super::Aggregation::Avg,
+                group_by: Some("model_name"),
+            },
+            super::Mapping {
+                metricname: "vllm:num_requests_waiting",
+                labels: &none,
+                aggregation: super::Aggregation::Sum,
+                group_by: None,
+            },
+            super::Mapping {
+                metricname: "vllm:time_to_first_token_seconds",
+                labels: &none,
+                aggregation: super::Aggregation::Quantile(0.5),
+                group_by: None,
+            },
+        ];
+        let mut histograms = super::Histograms::default();
+
+        let aggregated = super::aggregate(&data([10, 20, 20]), &mappings, &mut histograms).unwrap();
+        assert_eq!(
+            aggregated,
+            [
+                super::Aggregated {
+                    value: Some(5.),
+                    groups: BTreeMap::new(),
+                },
+                super::Aggregated {
+                    value: Some(4.),
+                    groups: BTreeMap::from([("a".to_owned(), 3.), ("b".to_owned(), 4.)]),
+                },
+                super::Aggregated {
+                    value: Some(0.5),
+                    groups: BTreeMap::from([("a".to_owned(), 0.5)]),
+                },
+                super::Aggregated::default(),
+                super::Aggregated::default(),
+            ],
+        );
+
+        let aggregated = super::aggregate(&data([12, 26, 26]), &mappings, &mut histograms).unwrap();
+        assert_eq!(aggregated[4].value, Some(0.625));
+    }
+
+    #[test]
+    fn test_histogram_quantile() {
+        let buckets = vec![(0.1, 10.), (0.5, 50.), (1., 90.), (f64::INFINITY, 100.)];
+        assert_eq!(super::histogram_quantile(0.5, buckets.clone()), Some(0.5));
+        assert_eq!(super::histogram_quantile(0.05, buckets.clone()), Some(0.05));
+        assert_eq!(super::histogram_quantile(0.7, buckets.clone()), Some(0.75));
+        assert_eq!(super::histogram_quantile(0.99, buckets.clone()), Some(1.));
+        assert_eq!(super::histogram_quantile(0.5, vec![(0.1, 1.)]), None);
+        assert_eq!(
+            super::histogram_quantile(0.5, vec![(f64::INFINITY, 0.)]),
+            None,
+        );
+        assert_eq!(
+            super::histogram_quantile(0.5, vec![(1., 1.), (1., 1.), (f64::INFINITY, 4.)]),
+            Some(1.),
+        );
+    }
 }
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(tag = "object", rename = "list")]
@@ -10,15 +11,66 @@ pub struct List<T> {
 #[serde(tag = "object", rename = "model")]
 pub struct Model {
     pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owned_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_model_len: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loaded: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub haori: Option<ModelStatus>,
     #[serde(flatten)]
     _extra: serde_json::Map<String, serde_json::Value>,
 }
 
+impl Model {
+    pub fn new(id: String) -> Self {
+        Self {
+            id,
+            owned_by: None,
+            root: None,
+            parent: None,
+            max_model_len: None,
+            loaded: None,
+            haori: None,
+            _extra: serde_json::Map::new(),
+        }
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.owned_by = self.owned_by.take().or_else(|| other.owned_by.clone());
+        self.root = self.root.take().or_else(|| other.root.clone());
+        self.parent = self.parent.take().or_else(|| other.parent.clone());
+        self.max_model_len = self.max_model_len.max(other.max_model_len);
+        self.loaded = self.loaded.max(other.loaded);
+        for (key, value) in &other._extra {
+            self._extra
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ModelStatus {
+    pub replicas: usize,
+    pub num_requests_running: u32,
+    pub num_requests_waiting: u32,
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Provider {
     pub id: uuid::Uuid,
     pub models: Vec<Model>,
     pub metrics: Metrics,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
@@ -27,4 +79,34 @@ pub struct Metrics {
     pub vllm_num_requests_running: Option<u32>,
     #[serde(rename = "vllm:num_requests_waiting")]
     pub vllm_num_requests_waiting: Option<u32>,
+    #[serde(rename = "vllm:kv_cache_usage_perc")]
+    pub vllm_kv_cache_usage_perc: Option<f64>,
+    #[serde(rename = "vllm:num_preemptions_total")]
+    pub vllm_num_preemptions_total: Option<u64>,
+    #[serde(rename = "vllm:num_preemptions_per_second")]
+    pub vllm_num_preemptions_per_second: Option<f64>,
+    #[serde(rename = "vllm:prompt_tokens_total")]
+    pub vllm_prompt_tokens_total: Option<u64>,
+    #[serde(rename = "vllm:generation_tokens_total")]
+    pub vllm_generation_tokens_total: Option<u64>,
+    #[serde(rename = "vllm:prompt_tokens_per_second")]
+    pub vllm_prompt_tokens_per_second: Option<f64>,
+    #[serde(rename = "vllm:generation_tokens_per_second")]
+    pub vllm_generation_tokens_per_second: Option<f64>,
+    #[serde(rename = "vllm:time_to_first_token_seconds:p50")]
+    pub vllm_time_to_first_token_seconds_p50: Option<f64>,
+    #[serde(rename = "vllm:time_to_first_token_seconds:p99")]
+    pub vllm_time_to_first_token_seconds_p99: Option<f64>,
+    #[serde(rename = "vllm:time_per_output_token_seconds:p50")]
+    pub vllm_time_per_output_token_seconds_p50: Option<f64>,
+    #[serde(rename = "vllm:time_per_output_token_seconds:p99")]
+    pub vllm_time_per_output_token_seconds_p99: Option<f64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub models: BTreeMap<String, Metrics>,
+}
+
+impl Metrics {
+    pub fn model(&self, model_id: &str) -> &Self {
+        self.models.get(model_id).unwrap_or(self)
+    }
 }